@@ -0,0 +1,191 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of the work done by `MultiCurrencyTrader` itself, to implement `weights::WeightInfo`
+//! with.
+//!
+//! The trader is not a pallet and its weight depends on its configuration (e.g. the price oracle),
+//! so it is benchmarked by the runtime hosting it. The runtime implements `Config` and adds the
+//! benchmarks via `add_benchmark!(params, batches, hydradx_adapters, TraderBench::<Runtime>)`,
+//! with `TraderBench` being `benchmarking::Pallet`.
+
+use frame_benchmarking::benchmarks;
+use polkadot_xcm::latest::prelude::*;
+use polkadot_xcm::latest::Weight;
+use sp_std::marker::PhantomData;
+use xcm_executor::{traits::WeightTrader, Assets};
+
+/// Maximum number of assets in a payment (respectively tracked by the trader) benchmarked.
+pub const MAX_ASSETS: u32 = 16;
+
+/// Weight bought with each asset.
+const WEIGHT: Weight = 1_000_000;
+
+/// Benchmarks of the trader configured by `Config`.
+pub struct Pallet<T: Config>(PhantomData<T>);
+
+pub trait Config: frame_system::Config {
+    /// The trader to benchmark, configured like the one used by the XCM executor.
+    type Trader: WeightTrader;
+
+    /// Get the location of the `i`th of `MAX_ASSETS` distinct assets that can pay for weight.
+    ///
+    /// The assets need a price, as the benchmarks buy weight with them.
+    fn asset_location(i: u32) -> MultiLocation;
+}
+
+/// Build a payment containing `amount` of each of the assets at `locations`.
+pub fn payment(locations: impl IntoIterator<Item = MultiLocation>, amount: u128) -> Assets {
+    let mut payment = Assets::new();
+    for location in locations {
+        payment.subsume((Concrete(location), amount).into());
+    }
+    payment
+}
+
+/// Create a trader that bought `weight` with `amount` of each of the assets at `locations` (one
+/// buy per asset), so it tracks all of them.
+pub fn trader_tracking<T: WeightTrader>(
+    locations: impl IntoIterator<Item = MultiLocation>,
+    weight: Weight,
+    amount: u128,
+) -> Result<T, XcmError> {
+    let mut trader = T::new();
+    for location in locations {
+        trader.buy_weight(weight, payment([location], amount))?;
+    }
+    Ok(trader)
+}
+
+benchmarks! {
+    buy_weight {
+        let n in 1 .. MAX_ASSETS;
+        let payment = payment((0..n).map(T::asset_location), u128::MAX);
+        let mut trader = T::Trader::new();
+    }: {
+        trader.buy_weight(WEIGHT, payment).map_err(|_| "buy_weight failed")?;
+    }
+
+    refund_weight {
+        let n in 1 .. MAX_ASSETS;
+        let mut trader = trader_tracking::<T::Trader>((0..n).map(T::asset_location), WEIGHT, u128::MAX)
+            .map_err(|_| "buy_weight failed")?;
+        let mut refund = None;
+    }: {
+        refund = trader.refund_weight(WEIGHT / 2);
+    }
+    verify {
+        assert!(refund.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockConvert, MockOracle};
+    use crate::MultiCurrencyTrader;
+    use frame_benchmarking::impl_benchmark_test_suite;
+    use frame_support::{
+        parameter_types,
+        traits::{Contains, Everything},
+        weights::IdentityFee,
+    };
+    use sp_core::H256;
+    use sp_runtime::testing::Header;
+    use sp_runtime::traits::{BlakeTwo256, Convert, IdentityLookup};
+    use sp_runtime::{FixedPointNumber, FixedU128};
+
+    type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+        {
+            System: frame_system,
+        }
+    );
+
+    parameter_types! {
+        pub const BlockHashCount: u64 = 250;
+        pub Prices: Vec<(u32, FixedU128)> =
+            (0..MAX_ASSETS).map(|id| (id, FixedU128::saturating_from_integer(id + 1))).collect();
+    }
+
+    impl frame_system::Config for Test {
+        type BaseCallFilter = Everything;
+        type BlockWeights = ();
+        type BlockLength = ();
+        type Origin = Origin;
+        type Call = Call;
+        type Index = u64;
+        type BlockNumber = u64;
+        type Hash = H256;
+        type Hashing = BlakeTwo256;
+        type AccountId = u64;
+        type Lookup = IdentityLookup<Self::AccountId>;
+        type Header = Header;
+        type Event = Event;
+        type BlockHashCount = BlockHashCount;
+        type DbWeight = ();
+        type Version = ();
+        type PalletInfo = PalletInfo;
+        type AccountData = ();
+        type OnNewAccount = ();
+        type OnKilledAccount = ();
+        type SystemWeightInfo = ();
+        type SS58Prefix = ();
+        type OnSetCode = ();
+        type MaxConsumers = frame_support::traits::ConstU32<16>;
+    }
+
+    pub struct KnownAssets;
+    impl Contains<u32> for KnownAssets {
+        fn contains(id: &u32) -> bool {
+            *id < MAX_ASSETS
+        }
+    }
+
+    type AssetConvert = MockConvert<u32, KnownAssets>;
+
+    impl Config for Test {
+        type Trader = MultiCurrencyTrader<
+            u32,
+            u128,
+            FixedU128,
+            IdentityFee<u128>,
+            MockOracle<u32, FixedU128, Prices>,
+            AssetConvert,
+            (),
+        >;
+
+        fn asset_location(i: u32) -> MultiLocation {
+            AssetConvert::convert(i).expect("asset is known")
+        }
+    }
+
+    fn new_test_ext() -> sp_io::TestExternalities {
+        frame_system::GenesisConfig::default()
+            .build_storage::<Test>()
+            .unwrap()
+            .into()
+    }
+
+    impl_benchmark_test_suite!(Pallet, super::new_test_ext(), super::Test);
+}
@@ -0,0 +1,379 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Configuration of `MultiCurrencyTrader` beyond pricing and revenue.
+//!
+//! `()` configures the default behaviour. Use `trader_config!` to only configure what differs from
+//! the defaults:
+//!
+//! ```
+//! use frame_support::parameter_types;
+//! use hydradx_adapters::trader_config;
+//! use sp_runtime::{FixedU128, Permill};
+//!
+//! parameter_types! {
+//!     pub NativeShare: Permill = Permill::from_percent(50);
+//! }
+//!
+//! pub struct Config;
+//! trader_config! {
+//!     impl TraderConfig<u32, u128, FixedU128> for Config {
+//!         type NativeFeeShare = NativeShare;
+//!     }
+//! }
+//! ```
+
+use frame_support::traits::{Contains, Get};
+use frame_support::weights::{Weight as WeightV2, WeightToFee};
+use polkadot_xcm::latest::prelude::*;
+use polkadot_xcm::latest::Weight;
+use sp_runtime::{
+    traits::{Convert, Saturating},
+    FixedPointNumber, Permill,
+};
+
+use crate::oracles::NativePriceOracleWithTime;
+use crate::selector::AssetSelector;
+use crate::weights::WeightInfo;
+use crate::{HandleOverflow, Observer};
+
+/// Configuration of `MultiCurrencyTrader`, see the associated types.
+pub trait TraderConfig<AssetId, Balance, Price> {
+    /// Charged amounts are rounded up to a per-asset increment (refunds are rounded down to the
+    /// same increment). Defaults to no rounding.
+    type ChargeIncrement: Convert<AssetId, Option<u128>>;
+
+    /// The weight processed by a single `refund_weight` call (in each dimension), so refunding more
+    /// than that requires multiple calls. Defaults to no cap.
+    type MaxRefundWeightPerCall: Get<Weight>;
+
+    /// If enabled, the refund that brings the bought weight down to zero also returns any
+    /// remainder left behind by rounding, instead of passing it on as revenue. Does not apply once
+    /// the trader charged for its own weight (see `WeightInfo`).
+    type SweepDustToRefund: Get<bool>;
+
+    /// The location of the native asset.
+    type NativeAssetLocation: Get<Option<MultiLocation>>;
+
+    /// The share of the bought weight charged in the native asset at `NativeAssetLocation`, with the
+    /// remainder being charged in the presented asset. As the `WeightTrader` interface does not
+    /// provide the origin, the native share is taken from the native asset contained in `payment`.
+    /// Defaults to charging the whole fee in the presented asset.
+    type NativeFeeShare: Get<Permill>;
+
+    /// Chooses the asset that pays for weight. Defaults to the first priced asset in the payment.
+    ///
+    /// Selection operates on the canonical `Assets` representation, so the same assets are charged
+    /// the same way regardless of their order in the payment.
+    type Selector: AssetSelector<Price>;
+
+    /// If enabled, the native asset at `NativeAssetLocation` always pays for weight when it is
+    /// contained in the payment, at a price of one and without querying the price oracle.
+    /// `Selector` is only consulted if the native asset is absent.
+    type PreferNativeAsset: Get<bool>;
+
+    /// Only assets contained in `AcceptedAssets` and not contained in `DeniedAssets` can pay for
+    /// weight, regardless of whether they have a price. Defaults to accepting all assets.
+    type AcceptedAssets: Contains<AssetId>;
+
+    /// See `AcceptedAssets`.
+    type DeniedAssets: Contains<AssetId>;
+
+    /// Scales bought and refunded weight before it is priced, e.g. to account for XCM specific
+    /// overhead. Defaults to no scaling.
+    type WeightScale: Convert<Weight, Weight>;
+
+    /// Increases fees charged in any asset other than the native asset at `NativeAssetLocation`
+    /// (e.g. `0.1` for 10%) to offset price risk. Refunds include the surcharge. Defaults to no
+    /// surcharge.
+    type Surcharge: Get<Price>;
+
+    /// If enabled, the fee converted to the paying asset is rounded up instead of being truncated.
+    /// Refunds are rounded in the opposite direction (but never exceed what was paid), so by
+    /// default refunding all bought weight does not leave dust behind.
+    type RoundFeeUp: Get<bool>;
+
+    /// The minimum charged per asset paying for weight (in units of the paying asset). Defaults to
+    /// no minimum. Regardless, buying any non-zero weight charges at least one unit.
+    type MinimumFee: Get<Balance>;
+
+    /// Buys that would charge more than `MaxFee` (in units of the paying asset) fail instead, to
+    /// protect against price spikes. Defaults to no maximum.
+    type MaxFee: Get<Option<Balance>>;
+
+    /// The unit of `Now` and `MaxPriceAge`, e.g. a block number or a timestamp. Defaults to `u64`.
+    type Moment: Saturating + PartialOrd;
+
+    /// Reports the prices of assets together with when they were last updated, see `MaxPriceAge`.
+    /// Defaults to no prices.
+    type TimedPrices: NativePriceOracleWithTime<AssetId, Price, Self::Moment>;
+
+    /// If set, assets other than the native asset at `NativeAssetLocation` are priced by
+    /// `TimedPrices` instead of `AcceptedCurrencyPrices`, and prices last updated more than
+    /// `MaxPriceAge` before `Now` are rejected. Buys that cannot be paid with any other asset fail
+    /// with `STALE_PRICE`. Defaults to no maximum age.
+    type MaxPriceAge: Get<Option<Self::Moment>>;
+
+    /// The current moment, see `MaxPriceAge`.
+    type Now: Get<Self::Moment>;
+
+    /// Prices the proof size of weight (passed as reference time). Defaults to `SameAsRefTime`,
+    /// i.e. to the `ConvertWeightToFee` of the trader.
+    type ConvertProofSizeToFee: ProofSizeToFee<Balance>;
+
+    /// Handles overflows when converting fees. Defaults to failing with `XcmError::Overflow`.
+    type OverflowPolicy: HandleOverflow;
+
+    /// `refund_weight` refunds the tracked assets in the order of their locations. If enabled, the
+    /// most recently paid asset is refunded first instead.
+    type LifoRefunds: Get<bool>;
+
+    /// Multiplies the fee in the native asset before it is converted to the paying asset, e.g. to
+    /// follow the `NextFeeMultiplier` of `pallet_transaction_payment` on congestion. Refunds use the
+    /// multiplier in effect at the time of the refund. Defaults to one.
+    type FeeMultiplier: Get<Price>;
+
+    /// Sets the fee per unit of weight (in the native asset, for both dimensions) for specific
+    /// assets, replacing `ConvertWeightToFee` and `ConvertProofSizeToFee` for them, e.g. to give a
+    /// discount for paying with a stablecoin. Refunds use the rate the refunded asset was paid at.
+    /// Defaults to no overrides.
+    type FeeRateOverride: Convert<AssetId, Option<Price>>;
+
+    /// Fees converted to assets with different decimals than the native asset at
+    /// `NativeAssetLocation` are scaled by `10^(asset decimals - native decimals)`. Defaults to no
+    /// scaling (which is also the case if the decimals of either asset are unknown).
+    type Decimals: Convert<AssetId, Option<u8>>;
+
+    /// Buying more than `MaxBuyWeight` (in either dimension, before scaling, including the weight of
+    /// the buy itself, see `WeightInfo`) at once fails with `XcmError::WeightLimitReached`, to bound
+    /// the fees a single buy can incur. Defaults to no limit.
+    type MaxBuyWeight: Get<Weight>;
+
+    /// If enabled, a payment that cannot cover the weight buys as much of it as it can afford
+    /// instead of failing with `XcmError::TooExpensive`, leaving the shortfall to the executor.
+    /// Defaults to strict buys.
+    type PartialBuy: Get<bool>;
+
+    /// If enabled, the surplus a buy charges beyond the value of the bought weight (e.g. because of
+    /// rounding, `ChargeIncrement` or `MinimumFee`) is credited per asset and price and offsets
+    /// the surplus of later buys paid with the same asset at the same price, so the trader does not
+    /// charge the surplus repeatedly, and can make up for a payment that falls short of the fee by
+    /// at most the credit. Credit is only used for buys without a `NativeFeeShare`, is not rounded
+    /// to `ChargeIncrement` and is dropped once the asset is refunded completely. Defaults to no
+    /// credit.
+    type AccumulateCredit: Get<bool>;
+
+    /// Notified about every amount charged and refunded, e.g. to update metrics. Defaults to no
+    /// observer. The trader cannot tell whether `Revenue` took the revenue, so revenue is observed
+    /// by `Revenue` instead (see `revenue::ToAccount`).
+    type FeeObserver: Observer;
+
+    /// Provides the weight of the work done by the trader itself (see `weights`). The weight of a
+    /// buy is charged on top of the bought weight and is not refundable, while the weight of a
+    /// refund is deducted from the refunded weight. Defaults to no weight.
+    type WeightInfo: WeightInfo;
+}
+
+crate::trader_config! {
+    impl<AssetId, Balance: Default, Price: FixedPointNumber> TraderConfig<AssetId, Balance, Price> for () {}
+}
+
+/// The defaults of the associated types of `TraderConfig`.
+pub mod defaults {
+    use frame_support::traits::{ConstU64, Everything, Nothing};
+    use polkadot_xcm::latest::Weight;
+    use sp_runtime::traits::Identity;
+
+    use crate::selector::FirstAssetSelector;
+    use crate::{AbortOnOverflow, UnitFeeMultiplier};
+
+    pub type ChargeIncrement = ();
+    pub type MaxRefundWeightPerCall = ConstU64<{ Weight::MAX }>;
+    pub type SweepDustToRefund = ();
+    pub type NativeAssetLocation = ();
+    pub type NativeFeeShare = ();
+    pub type Selector = FirstAssetSelector;
+    pub type PreferNativeAsset = ();
+    pub type AcceptedAssets = Everything;
+    pub type DeniedAssets = Nothing;
+    pub type WeightScale = Identity;
+    pub type Surcharge = ();
+    pub type RoundFeeUp = ();
+    pub type MinimumFee = ();
+    pub type MaxFee = ();
+    pub type Moment = u64;
+    pub type TimedPrices = ();
+    pub type MaxPriceAge = ();
+    pub type Now = ();
+    pub type ConvertProofSizeToFee = super::SameAsRefTime;
+    pub type OverflowPolicy = AbortOnOverflow;
+    pub type LifoRefunds = ();
+    pub type FeeMultiplier = UnitFeeMultiplier;
+    pub type FeeRateOverride = ();
+    pub type Decimals = ();
+    pub type MaxBuyWeight = ConstU64<{ Weight::MAX }>;
+    pub type PartialBuy = ();
+    pub type AccumulateCredit = ();
+    pub type FeeObserver = ();
+    pub type WeightInfo = ();
+}
+
+/// Implements `TraderConfig` for a type, using the `defaults` for all associated types that are not
+/// given. The implementation can be generic:
+///
+/// ```ignore
+/// pub struct Config<Selector>(PhantomData<Selector>);
+/// trader_config! {
+///     impl<Selector: AssetSelector<FixedU128>> TraderConfig<u32, u128, FixedU128> for Config<Selector> {
+///         type Selector = Selector;
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! trader_config {
+    (
+        impl $(<$($generic:ident $(: $bound:path)?),*>)? TraderConfig<$asset_id:ty, $balance:ty, $price:ty>
+            for $config:ty { $(type $name:ident = $value:ty;)* }
+    ) => {
+        const _: () = {
+            $(let _ = $crate::config::Knob::$name;)*
+        };
+
+        impl $(<$($generic $(: $bound)?),*>)? $crate::config::TraderConfig<$asset_id, $balance, $price> for $config {
+            type ChargeIncrement = $crate::trader_config!(@pick ChargeIncrement; $(type $name = $value;)*);
+            type MaxRefundWeightPerCall = $crate::trader_config!(@pick MaxRefundWeightPerCall; $(type $name = $value;)*);
+            type SweepDustToRefund = $crate::trader_config!(@pick SweepDustToRefund; $(type $name = $value;)*);
+            type NativeAssetLocation = $crate::trader_config!(@pick NativeAssetLocation; $(type $name = $value;)*);
+            type NativeFeeShare = $crate::trader_config!(@pick NativeFeeShare; $(type $name = $value;)*);
+            type Selector = $crate::trader_config!(@pick Selector; $(type $name = $value;)*);
+            type PreferNativeAsset = $crate::trader_config!(@pick PreferNativeAsset; $(type $name = $value;)*);
+            type AcceptedAssets = $crate::trader_config!(@pick AcceptedAssets; $(type $name = $value;)*);
+            type DeniedAssets = $crate::trader_config!(@pick DeniedAssets; $(type $name = $value;)*);
+            type WeightScale = $crate::trader_config!(@pick WeightScale; $(type $name = $value;)*);
+            type Surcharge = $crate::trader_config!(@pick Surcharge; $(type $name = $value;)*);
+            type RoundFeeUp = $crate::trader_config!(@pick RoundFeeUp; $(type $name = $value;)*);
+            type MinimumFee = $crate::trader_config!(@pick MinimumFee; $(type $name = $value;)*);
+            type MaxFee = $crate::trader_config!(@pick MaxFee; $(type $name = $value;)*);
+            type Moment = $crate::trader_config!(@pick Moment; $(type $name = $value;)*);
+            type TimedPrices = $crate::trader_config!(@pick TimedPrices; $(type $name = $value;)*);
+            type MaxPriceAge = $crate::trader_config!(@pick MaxPriceAge; $(type $name = $value;)*);
+            type Now = $crate::trader_config!(@pick Now; $(type $name = $value;)*);
+            type ConvertProofSizeToFee =
+                $crate::trader_config!(@pick ConvertProofSizeToFee; $(type $name = $value;)*);
+            type OverflowPolicy = $crate::trader_config!(@pick OverflowPolicy; $(type $name = $value;)*);
+            type LifoRefunds = $crate::trader_config!(@pick LifoRefunds; $(type $name = $value;)*);
+            type FeeMultiplier = $crate::trader_config!(@pick FeeMultiplier; $(type $name = $value;)*);
+            type FeeRateOverride = $crate::trader_config!(@pick FeeRateOverride; $(type $name = $value;)*);
+            type Decimals = $crate::trader_config!(@pick Decimals; $(type $name = $value;)*);
+            type MaxBuyWeight = $crate::trader_config!(@pick MaxBuyWeight; $(type $name = $value;)*);
+            type PartialBuy = $crate::trader_config!(@pick PartialBuy; $(type $name = $value;)*);
+            type AccumulateCredit = $crate::trader_config!(@pick AccumulateCredit; $(type $name = $value;)*);
+            type FeeObserver = $crate::trader_config!(@pick FeeObserver; $(type $name = $value;)*);
+            type WeightInfo = $crate::trader_config!(@pick WeightInfo; $(type $name = $value;)*);
+        }
+    };
+    (@pick ChargeIncrement; type ChargeIncrement = $value:ty; $($rest:tt)*) => { $value };
+    (@pick MaxRefundWeightPerCall; type MaxRefundWeightPerCall = $value:ty; $($rest:tt)*) => { $value };
+    (@pick SweepDustToRefund; type SweepDustToRefund = $value:ty; $($rest:tt)*) => { $value };
+    (@pick NativeAssetLocation; type NativeAssetLocation = $value:ty; $($rest:tt)*) => { $value };
+    (@pick NativeFeeShare; type NativeFeeShare = $value:ty; $($rest:tt)*) => { $value };
+    (@pick Selector; type Selector = $value:ty; $($rest:tt)*) => { $value };
+    (@pick PreferNativeAsset; type PreferNativeAsset = $value:ty; $($rest:tt)*) => { $value };
+    (@pick AcceptedAssets; type AcceptedAssets = $value:ty; $($rest:tt)*) => { $value };
+    (@pick DeniedAssets; type DeniedAssets = $value:ty; $($rest:tt)*) => { $value };
+    (@pick WeightScale; type WeightScale = $value:ty; $($rest:tt)*) => { $value };
+    (@pick Surcharge; type Surcharge = $value:ty; $($rest:tt)*) => { $value };
+    (@pick RoundFeeUp; type RoundFeeUp = $value:ty; $($rest:tt)*) => { $value };
+    (@pick MinimumFee; type MinimumFee = $value:ty; $($rest:tt)*) => { $value };
+    (@pick MaxFee; type MaxFee = $value:ty; $($rest:tt)*) => { $value };
+    (@pick Moment; type Moment = $value:ty; $($rest:tt)*) => { $value };
+    (@pick TimedPrices; type TimedPrices = $value:ty; $($rest:tt)*) => { $value };
+    (@pick MaxPriceAge; type MaxPriceAge = $value:ty; $($rest:tt)*) => { $value };
+    (@pick Now; type Now = $value:ty; $($rest:tt)*) => { $value };
+    (@pick ConvertProofSizeToFee; type ConvertProofSizeToFee = $value:ty; $($rest:tt)*) => { $value };
+    (@pick OverflowPolicy; type OverflowPolicy = $value:ty; $($rest:tt)*) => { $value };
+    (@pick LifoRefunds; type LifoRefunds = $value:ty; $($rest:tt)*) => { $value };
+    (@pick FeeMultiplier; type FeeMultiplier = $value:ty; $($rest:tt)*) => { $value };
+    (@pick FeeRateOverride; type FeeRateOverride = $value:ty; $($rest:tt)*) => { $value };
+    (@pick Decimals; type Decimals = $value:ty; $($rest:tt)*) => { $value };
+    (@pick MaxBuyWeight; type MaxBuyWeight = $value:ty; $($rest:tt)*) => { $value };
+    (@pick PartialBuy; type PartialBuy = $value:ty; $($rest:tt)*) => { $value };
+    (@pick AccumulateCredit; type AccumulateCredit = $value:ty; $($rest:tt)*) => { $value };
+    (@pick FeeObserver; type FeeObserver = $value:ty; $($rest:tt)*) => { $value };
+    (@pick WeightInfo; type WeightInfo = $value:ty; $($rest:tt)*) => { $value };
+    (@pick $knob:ident; type $other:ident = $value:ty; $($rest:tt)*) => {
+        $crate::trader_config!(@pick $knob; $($rest)*)
+    };
+    (@pick $knob:ident;) => { $crate::config::defaults::$knob };
+}
+
+/// The associated types of `TraderConfig`, to reject unknown names in `trader_config!`.
+#[doc(hidden)]
+pub enum Knob {
+    ChargeIncrement,
+    MaxRefundWeightPerCall,
+    SweepDustToRefund,
+    NativeAssetLocation,
+    NativeFeeShare,
+    Selector,
+    PreferNativeAsset,
+    AcceptedAssets,
+    DeniedAssets,
+    WeightScale,
+    Surcharge,
+    RoundFeeUp,
+    MinimumFee,
+    MaxFee,
+    Moment,
+    TimedPrices,
+    MaxPriceAge,
+    Now,
+    ConvertProofSizeToFee,
+    OverflowPolicy,
+    LifoRefunds,
+    FeeMultiplier,
+    FeeRateOverride,
+    Decimals,
+    MaxBuyWeight,
+    PartialBuy,
+    AccumulateCredit,
+    FeeObserver,
+    WeightInfo,
+}
+
+/// Prices the proof size of weight, see `TraderConfig::ConvertProofSizeToFee`.
+///
+/// Implemented by every `WeightToFee`, which is passed the proof size as reference time.
+pub trait ProofSizeToFee<Balance> {
+    /// Get the fee for `proof_size`, or `None` to price it like reference time.
+    fn proof_size_to_fee(proof_size: u64) -> Option<Balance>;
+}
+
+/// Prices the proof size like the reference time, i.e. with the `ConvertWeightToFee` of the trader.
+pub struct SameAsRefTime;
+
+impl<Balance> ProofSizeToFee<Balance> for SameAsRefTime {
+    fn proof_size_to_fee(_proof_size: u64) -> Option<Balance> {
+        None
+    }
+}
+
+impl<F: WeightToFee> ProofSizeToFee<F::Balance> for F {
+    fn proof_size_to_fee(proof_size: u64) -> Option<F::Balance> {
+        Some(F::weight_to_fee(&WeightV2::from_ref_time(proof_size)))
+    }
+}
@@ -0,0 +1,42 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use frame_support::traits::tokens::{
+    fungibles::{Inspect, Mutate},
+    WithdrawConsequence,
+};
+use pallet_transaction_multi_payment::DepositFee;
+use sp_runtime::{traits::Zero, DispatchResult};
+use sp_std::marker::PhantomData;
+
+/// Implements `DepositFee` by minting the fee into an account of `pallet_assets` (instance `I`).
+///
+/// Fees in a frozen asset are not deposited and fail with `Frozen`. Other errors of the deposit
+/// (e.g. an unknown asset or an amount below the minimum balance) are returned as is.
+pub struct PalletAssetsDepositFee<T, I = ()>(PhantomData<(T, I)>);
+
+impl<T: pallet_assets::Config<I>, I: 'static> DepositFee<T::AccountId, T::AssetId, T::Balance>
+    for PalletAssetsDepositFee<T, I>
+{
+    fn deposit_fee(who: &T::AccountId, currency: T::AssetId, amount: T::Balance) -> DispatchResult {
+        // Minting does not check whether the asset is frozen, withdrawing (even nothing) does.
+        if let WithdrawConsequence::Frozen = pallet_assets::Pallet::<T, I>::can_withdraw(currency, who, Zero::zero()) {
+            return Err(pallet_assets::Error::<T, I>::Frozen.into());
+        }
+        pallet_assets::Pallet::<T, I>::mint_into(currency, who, amount)
+    }
+}
@@ -0,0 +1,35 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use orml_traits::MultiCurrency;
+use pallet_transaction_multi_payment::DepositFee;
+use sp_runtime::{traits::Convert, DispatchResult};
+use sp_std::marker::PhantomData;
+
+/// Implements `DepositFee` by depositing the fee into an account of `orml_tokens`.
+///
+/// The asset id is converted to the currency id of `orml_tokens` by `GetCurrencyId`. Errors of
+/// the deposit (e.g. an amount below the existential deposit) are returned as is.
+pub struct OrmlTokensDepositFee<T, GetCurrencyId>(PhantomData<(T, GetCurrencyId)>);
+
+impl<T: orml_tokens::Config, AssetId, GetCurrencyId: Convert<AssetId, T::CurrencyId>>
+    DepositFee<T::AccountId, AssetId, T::Balance> for OrmlTokensDepositFee<T, GetCurrencyId>
+{
+    fn deposit_fee(who: &T::AccountId, currency: AssetId, amount: T::Balance) -> DispatchResult {
+        orml_tokens::Pallet::<T>::deposit(GetCurrencyId::convert(currency), who, amount)
+    }
+}
@@ -2,6 +2,20 @@ use frame_support::pallet_prelude::Get;
 use frame_support::traits::fungible::Inspect as FungibleInspect;
 use frame_support::traits::fungibles::Inspect as FungiblesInspect;
 use frame_support::traits::tokens::{DepositConsequence, WithdrawConsequence};
+use frame_support::weights::{Weight as WeightV2, WeightToFee};
+use hydradx_traits::NativePriceOracle;
+use polkadot_xcm::latest::prelude::*;
+use polkadot_xcm::latest::Weight;
+use sp_runtime::{
+    traits::{Convert, Zero},
+    FixedPointNumber, FixedPointOperand,
+};
+use sp_std::vec::Vec;
+use xcm_builder::TakeRevenue;
+
+use crate::config::TraderConfig;
+use crate::weights::WeightInfo;
+use crate::{AssetIds, MultiCurrencyTrader};
 
 /// An adapter to use inspect functionality for both native and multi currency
 pub struct MultiInspectAdapter<AccountId, AssetId, Balance, NativeCurrency, MultiCurrency, GetNativeCurrencyId>(
@@ -79,3 +93,194 @@ where
         }
     }
 }
+
+/// An asset that can pay for weight, with its price and the amount required.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeOption<Price> {
+    pub location: MultiLocation,
+    pub price: Price,
+    pub amount: u128,
+}
+
+impl<
+        AssetId: Clone,
+        Balance: FixedPointOperand + TryInto<u128>,
+        Price: FixedPointNumber,
+        ConvertWeightToFee: WeightToFee<Balance = Balance>,
+        AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
+        ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
+        Revenue: TakeRevenue,
+        Config: TraderConfig<AssetId, Balance, Price>,
+    >
+    MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConvertWeightToFee,
+        AcceptedCurrencyPrices,
+        ConvertCurrency,
+        Revenue,
+        Config,
+    >
+{
+    /// Preview the settlement for a hypothetical refund of `refund_weight` followed by dropping
+    /// the trader, without changing any state.
+    ///
+    /// Returns the asset that `refund_weight` would return and the assets that would then be
+    /// passed to `Revenue` on `Drop`.
+    pub fn preview_settlement(&self, refund_weight: Weight) -> (Option<MultiAsset>, Vec<MultiAsset>) {
+        let (_, refund) = self.refund_for(WeightV2::from_ref_time(refund_weight));
+        let refund = refund.filter(|(_, _, refund)| !refund.is_zero());
+        let revenue = self
+            .paid_assets
+            .iter()
+            .filter_map(|((asset_loc, price), paid)| {
+                let amount = match &refund {
+                    Some((refund_loc, refund_price, refund)) if refund_loc == asset_loc && refund_price == price => {
+                        paid.amount - refund
+                    }
+                    _ => paid.amount,
+                };
+                (!amount.is_zero()).then(|| (asset_loc.clone(), amount).into())
+            })
+            .collect();
+        let refund = refund.map(|(asset_loc, _, refund)| (Concrete(asset_loc), refund).into());
+        (refund, revenue)
+    }
+
+    /// Get `weight` with the weight of buying it with a single asset (see `WeightInfo`) added, as
+    /// charged by `buy_weight`.
+    fn single_asset_buy(weight: Weight) -> WeightV2 {
+        WeightV2::from_ref_time(weight).saturating_add(Config::WeightInfo::buy_weight(1))
+    }
+
+    /// Get the fee options for `weight` among the assets at `candidates`.
+    ///
+    /// Assets that are not accepted or have no price are omitted.
+    pub fn fee_options(candidates: &[MultiLocation], weight: Weight) -> Vec<FeeOption<Price>> {
+        let native = Config::NativeAssetLocation::get();
+        let ids = AssetIds::new(native.as_ref());
+        candidates
+            .iter()
+            .filter_map(|location| {
+                let price = Self::price_of(&ids, location)?;
+                let amount =
+                    Self::compute_fee_with(&ids, Self::scale(Self::single_asset_buy(weight)), location, price).ok()?;
+                Some(FeeOption {
+                    location: location.clone(),
+                    price,
+                    amount,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the price of the asset at `location` and the amount of it charged for a single unit of
+    /// weight, priced the same way as by `buy_weight` (e.g. including `WeightScale`,
+    /// `FeeMultiplier`, `FeeRateOverride`, `Decimals` and `Surcharge`).
+    ///
+    /// Returns `None` if the asset is not accepted, has no price or cannot be charged.
+    pub fn marginal_rate(location: &MultiLocation) -> Option<(Price, u128)> {
+        let native = Config::NativeAssetLocation::get();
+        let ids = AssetIds::new(native.as_ref());
+        let price = Self::price_of(&ids, location)?;
+        let amount = Self::compute_fee_with(&ids, Self::scale(WeightV2::from_ref_time(1)), location, price).ok()?;
+        Some((price, amount))
+    }
+
+    /// Resolve an asset returned by the trader (e.g. a refund) to its id and balance, using
+    /// `ConvertCurrency`.
+    ///
+    /// Returns `None` if the asset is not fungible, unknown or its amount does not fit `Balance`.
+    pub fn asset_id_and_balance(asset: &MultiAsset) -> Option<(AssetId, Balance)> {
+        let amount = match asset.fun {
+            Fungible(amount) => Balance::try_from(amount).ok()?,
+            NonFungible(_) => return None,
+        };
+        let asset_id = ConvertCurrency::convert(asset.clone())?;
+        Some((asset_id, amount))
+    }
+}
+
+/// Estimates the fee charged for weight in a given asset.
+pub trait EstimateFee {
+    /// Get the amount of the asset at `location` that would be charged for `weight`.
+    ///
+    /// Returns `None` if the asset cannot be used to pay for weight.
+    fn estimate_fee(weight: Weight, location: &MultiLocation) -> Option<u128>;
+}
+
+impl<
+        AssetId: Clone,
+        Balance: FixedPointOperand + TryInto<u128>,
+        Price: FixedPointNumber,
+        ConvertWeightToFee: WeightToFee<Balance = Balance>,
+        AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
+        ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
+        Revenue: TakeRevenue,
+        Config: TraderConfig<AssetId, Balance, Price>,
+    > EstimateFee
+    for MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConvertWeightToFee,
+        AcceptedCurrencyPrices,
+        ConvertCurrency,
+        Revenue,
+        Config,
+    >
+{
+    fn estimate_fee(weight: Weight, location: &MultiLocation) -> Option<u128> {
+        let native = Config::NativeAssetLocation::get();
+        let ids = AssetIds::new(native.as_ref());
+        let price = Self::price_of(&ids, location)?;
+        Self::compute_fee_with(&ids, Self::scale(Self::single_asset_buy(weight)), location, price).ok()
+    }
+}
+
+/// Difference between the fees charged by two fee configurations.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeDelta {
+    pub weight: Weight,
+    pub location: MultiLocation,
+    /// Fee charged by the old configuration, `None` if the asset could not pay.
+    pub old_fee: Option<u128>,
+    /// Fee charged by the new configuration, `None` if the asset could not pay.
+    pub new_fee: Option<u128>,
+}
+
+#[cfg(feature = "std")]
+impl FeeDelta {
+    /// Get the change in fee from the old to the new configuration.
+    ///
+    /// Returns `None` if either configuration cannot charge the asset.
+    pub fn difference(&self) -> Option<i128> {
+        let old_fee = i128::try_from(self.old_fee?).ok()?;
+        let new_fee = i128::try_from(self.new_fee?).ok()?;
+        new_fee.checked_sub(old_fee)
+    }
+}
+
+/// Compare the fees charged by the `Old` and `New` fee configurations for every combination of
+/// `weights` and `locations`.
+///
+/// Meant to help reviewing changes to fee curves or prices.
+#[cfg(feature = "std")]
+pub fn compare_fees<Old: EstimateFee, New: EstimateFee>(
+    weights: &[Weight],
+    locations: &[MultiLocation],
+) -> Vec<FeeDelta> {
+    weights
+        .iter()
+        .flat_map(|weight| {
+            locations.iter().map(move |location| FeeDelta {
+                weight: *weight,
+                location: location.clone(),
+                old_fee: Old::estimate_fee(*weight, location),
+                new_fee: New::estimate_fee(*weight, location),
+            })
+        })
+        .collect()
+}
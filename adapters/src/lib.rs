@@ -17,41 +17,97 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use frame_support::weights::WeightToFee;
+use config::TraderConfig;
+use frame_support::traits::{Contains, Everything, Get};
+use frame_support::weights::{Weight as WeightV2, WeightToFee};
 use hydradx_traits::NativePriceOracle;
-use pallet_transaction_multi_payment::{DepositFee, TransactionMultiPaymentDataProvider};
+use oracles::NativePriceOracleWithTime;
+use paid_assets::{BuyRecord, Paid, PaidAssets};
 use polkadot_xcm::latest::prelude::*;
 use polkadot_xcm::latest::Weight;
+use revenue::{FeeReceiverOf, TakeRevenueFrom, ToAccount};
+use selector::AssetSelector;
+use smallvec::SmallVec;
 use sp_runtime::{
-    traits::{AtLeast32BitUnsigned, Convert, Saturating, Zero},
-    FixedPointNumber, FixedPointOperand, SaturatedConversion,
+    helpers_128bit::multiply_by_rational_with_rounding,
+    traits::{Bounded, Convert, Saturating, Zero},
+    FixedPointNumber, FixedPointOperand, PerThing, Perquintill, Rounding, SaturatedConversion,
 };
-use sp_std::{collections::btree_map::BTreeMap, marker::PhantomData};
+use sp_std::{
+    cell::{Cell, RefCell},
+    collections::btree_map::BTreeMap,
+    marker::PhantomData,
+    vec::Vec,
+};
+use weights::WeightInfo;
 use xcm_builder::TakeRevenue;
 use xcm_executor::{traits::WeightTrader, Assets};
 
+#[cfg(feature = "runtime-benchmarks")]
+pub mod benchmarking;
+pub mod config;
+pub mod deposit;
 pub mod inspect;
+pub mod location;
+pub mod oracles;
+mod paid_assets;
+pub mod revenue;
+pub mod selector;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod weights;
 
 #[cfg(test)]
 mod tests;
 
+/// Identifier of a buy of a `MultiCurrencyTrader`, see `MultiCurrencyTrader::last_buy_id`.
+pub type BuyId = u32;
+
+/// The error of buys that could only be paid with assets whose price is older than `MaxPriceAge`
+/// (see `TraderConfig`). `XcmError` cannot be extended, so it is a distinct `FailedToTransactAsset`.
+pub const STALE_PRICE: XcmError = XcmError::FailedToTransactAsset("StalePrice");
+
 /// Weight trader that accepts multiple assets as weight fee payment.
 ///
 /// It uses `WeightToFee` in combination with a `NativePriceOracle` to set the right price for weight.
+/// Both the reference time and the proof size of the weight are tracked and charged (see
+/// `buy_weight_v2`), but the `WeightTrader` interface of XCM v2 only provides the reference time.
+/// The proof size is priced by `ConvertProofSizeToFee` (passed as reference time), which defaults
+/// to `ConvertWeightToFee`.
 /// Keeps track of the assets used to pay for weight and can refund them one by one (interface only
 /// allows returning one asset per refund). Will pass any remaining assets on `Drop` to
-/// `TakeRevenue`.
+/// `TakeRevenue`, consolidated to one asset per location (even if it was paid at different prices)
+/// and ordered by location.
+///
+/// Everything beyond pricing and revenue is configured by `Config` (see `TraderConfig`), which
+/// defaults to the behaviour described there.
 pub struct MultiCurrencyTrader<
-    AssetId,
+    AssetId: Clone,
     Balance: FixedPointOperand + TryInto<u128>,
     Price: FixedPointNumber,
     ConvertWeightToFee: WeightToFee<Balance = Balance>,
     AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
     ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
     Revenue: TakeRevenue,
+    Config: TraderConfig<AssetId, Balance, Price> = (),
 > {
-    weight: Weight,
-    paid_assets: BTreeMap<(MultiLocation, Price), u128>,
+    /// Refundable weight bought so far, minus the weight refunded.
+    weight: WeightV2,
+    /// Weight of the work done by the trader itself charged by the buys so far (see `WeightInfo`),
+    /// which is not refundable.
+    overhead: WeightV2,
+    /// The assets paid so far (minus refunds), each with what the buys paying for it bought.
+    paid_assets: PaidAssets<Price>,
+    buys: u32,
+    refunds: u32,
+    /// Whether the tracked revenue was already passed to `Revenue` by `flush_revenue`.
+    flushed: bool,
+    /// Total amounts charged per asset, to check that refunds never exceed them.
+    #[cfg(feature = "accounting-checks")]
+    charged: BTreeMap<MultiLocation, u128>,
+    /// Total amounts refunded per asset.
+    #[cfg(feature = "accounting-checks")]
+    refunded: BTreeMap<MultiLocation, u128>,
     _phantom: PhantomData<(
         AssetId,
         Balance,
@@ -60,44 +116,1086 @@ pub struct MultiCurrencyTrader<
         AcceptedCurrencyPrices,
         ConvertCurrency,
         Revenue,
+        Config,
     )>,
 }
 
 impl<
-        AssetId,
+        AssetId: Clone,
         Balance: FixedPointOperand + TryInto<u128>,
         Price: FixedPointNumber,
         ConvertWeightToFee: WeightToFee<Balance = Balance>,
         AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
         ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
         Revenue: TakeRevenue,
+        Config: TraderConfig<AssetId, Balance, Price>,
+    >
+    MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConvertWeightToFee,
+        AcceptedCurrencyPrices,
+        ConvertCurrency,
+        Revenue,
+        Config,
     >
-    MultiCurrencyTrader<AssetId, Balance, Price, ConvertWeightToFee, AcceptedCurrencyPrices, ConvertCurrency, Revenue>
 {
-    /// Get the asset id of the first asset in `payment` and try to determine its price via the
-    /// price oracle.
-    fn get_asset_and_price(&mut self, payment: &Assets) -> Option<(MultiLocation, Price)> {
-        if let Some(asset) = payment.fungible_assets_iter().next() {
-            ConvertCurrency::convert(asset.clone())
-                .and_then(|currency| AcceptedCurrencyPrices::price(currency))
-                .and_then(|price| match asset.id.clone() {
-                    Concrete(location) => Some((location, price)),
-                    _ => None,
-                })
+    /// Will try to buy `weight` (both reference time and proof size) with the asset in `payment`
+    /// chosen by `Selector` (by default the first one that has a price).
+    ///
+    /// The `BuyExecution` XCM instruction usually only passes one asset per buy, but `payment` may
+    /// contain several fungible assets.
+    /// The fee is determined by `ConvertWeightToFee` in combination with the price determined by
+    /// `AcceptedCurrencyPrices`, applied to the sum of the fees for both dimensions of `weight`.
+    ///
+    /// If `PartialBuy` is enabled, a payment that cannot cover `weight` buys as much of it as it can
+    /// afford, which is reflected by `bought_weight`.
+    ///
+    /// Buying no weight returns `payment` untouched, without charging or tracking anything.
+    ///
+    /// Fails with `XcmError::Overflow` if the trader has already made `u32::MAX` buys.
+    ///
+    /// With the `tracing` feature, the buy is wrapped in a span carrying the weight, the location of
+    /// the paying asset and the fee.
+    pub fn buy_weight_v2(&mut self, weight: WeightV2, payment: Assets) -> Result<Assets, XcmError> {
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::buy_weight_v2 weight: {:?}, payment: {:?}",
+            weight, payment
+        );
+        if weight.is_zero() {
+            return Ok(payment);
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!(
+            target: "xcm::weight",
+            "MultiCurrencyTrader::buy_weight_v2",
+            weight = ?weight,
+            location = tracing::field::Empty,
+            fee = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        let overhead = Self::scale(Self::buy_overhead(&payment));
+        let purchase = self.prepare_purchase(weight, &payment)?;
+        // Buy records are keyed by this id, so it must not repeat.
+        let id = self.buys.checked_add(1).ok_or(XcmError::Overflow)?;
+        #[cfg(feature = "tracing")]
+        {
+            span.record("location", &tracing::field::debug(&purchase.location));
+            span.record("fee", &purchase.amount);
+        }
+        let mut unused = payment;
+        for (location, amount) in purchase.payments() {
+            Self::subtract_payment(&mut unused, location, amount);
+        }
+        // The weight of the buy itself is charged, but neither available to the caller nor refundable.
+        let overhead = overhead.min(purchase.weight);
+        let bought = purchase.weight.saturating_sub(overhead);
+        self.weight = self.weight.saturating_add(bought);
+        self.overhead = self.overhead.saturating_add(overhead);
+        self.buys = id;
+        for (location, amount) in purchase.payments() {
+            Config::FeeObserver::on_buy(location, amount);
+        }
+        let record = BuyRecord {
+            id,
+            weight: bought,
+            amount: purchase.amount,
+        };
+        if let Some(paid) = self.track_payment(purchase.location, purchase.price, purchase.amount, purchase.fee_rate) {
+            paid.buys.push(record);
+            if let Some(credit) = purchase.credit {
+                paid.credit = credit;
+            }
+        }
+        if let Some((location, native_amount)) = purchase.native {
+            self.track_payment(location, Price::one(), native_amount, purchase.native_fee_rate);
+        }
+        Ok(unused)
+    }
+
+    /// Determine the asset and amount `buy_weight_v2` would charge for `weight` from `payment`,
+    /// without changing any state.
+    ///
+    /// Fails with the same error as `buy_weight_v2` if `payment` cannot cover `weight`.
+    pub fn peek_fee(&self, weight: WeightV2, payment: &Assets) -> Result<(MultiLocation, u128), XcmError> {
+        self.prepare_purchase(weight, payment)
+            .map(|purchase| (purchase.location, purchase.amount))
+    }
+
+    /// Determine the purchase of `weight` with `payment`, without committing it to the trader.
+    ///
+    /// If `PartialBuy` is enabled and `payment` cannot cover `weight`, the purchase is reduced to the
+    /// share of `weight` that `payment` can afford.
+    fn prepare_purchase(&self, weight: WeightV2, payment: &Assets) -> Result<Purchase<Price>, XcmError> {
+        if payment.fungible_assets_iter().next().is_none() && payment.non_fungible_assets_iter().next().is_some() {
+            // Reported as `AssetNotFound` like unknown assets, so `FallbackTrader` can still fall back,
+            // but logged distinctly.
+            log::trace!(target: "xcm::weight", "MultiCurrencyTrader::buy_weight_v2 cannot pay with non-fungible assets");
+            return Err(XcmError::AssetNotFound);
+        }
+        let weight = weight.saturating_add(Self::buy_overhead(payment));
+        let max_weight = Config::MaxBuyWeight::get();
+        if weight.ref_time() > max_weight || weight.proof_size() > max_weight {
+            log::trace!(target: "xcm::weight", "MultiCurrencyTrader::buy_weight_v2 weight exceeds the maximum");
+            return Err(XcmError::WeightLimitReached(weight.ref_time()));
+        }
+        let weight = Self::scale(weight);
+        // Convert each asset to its id only once during the purchase.
+        let native = Config::NativeAssetLocation::get();
+        let ids = AssetIds::new(native.as_ref());
+        let (asset_loc, price) = self.get_asset_and_price(&ids, weight, payment)?;
+        let mut purchase = Self::purchase_for(&ids, weight, asset_loc, price)?;
+        self.apply_credit(&ids, asset_loc, &mut purchase);
+        if Self::is_affordable(&purchase, payment) {
+            return Ok(purchase);
+        }
+        if !Config::PartialBuy::get() {
+            return Err(XcmError::TooExpensive);
+        }
+        // Buy the share of the weight the scarcest of the paying assets can afford.
+        let share = purchase
+            .payments()
+            .map(|(location, amount)| Perquintill::from_rational(Self::available(payment, location), amount))
+            .min()
+            .unwrap_or_else(Perquintill::zero);
+        let partial_weight =
+            WeightV2::from_parts(share.mul_floor(weight.ref_time()), share.mul_floor(weight.proof_size()));
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::buy_weight_v2 buying partial weight: {:?}", partial_weight
+        );
+        if partial_weight.is_zero() {
+            return Err(XcmError::TooExpensive);
+        }
+        let (amount, native) = Self::charges_for(&ids, partial_weight, asset_loc, price)?;
+        // Rounding (see `RoundFeeUp` and `ChargeIncrement`) can push the fee of the affordable share
+        // beyond the payment, so charge at most what was paid, but never less than `MinimumFee`.
+        let minimum: u128 = Config::MinimumFee::get().saturated_into();
+        purchase.weight = partial_weight;
+        purchase.amount = amount.min(Self::available(payment, asset_loc));
+        if let (Some((_, native_amount)), Some((location, amount))) = (purchase.native.as_mut(), native) {
+            *native_amount = amount.min(Self::available(payment, location));
+        }
+        if purchase.payments().any(|(_, amount)| amount < minimum) {
+            return Err(XcmError::TooExpensive);
+        }
+        self.apply_credit(&ids, asset_loc, &mut purchase);
+        Ok(purchase)
+    }
+
+    /// If `AccumulateCredit` applies to `purchase` (paid with the asset at `asset_loc`), offset the
+    /// surplus it charges beyond the value of its weight by the credit of its asset, and determine
+    /// the credit left afterwards (see `Purchase::credit`).
+    ///
+    /// At least one unit is always charged, so weight is never free.
+    fn apply_credit<'a>(
+        &self,
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        asset_loc: &'a MultiLocation,
+        purchase: &mut Purchase<Price>,
+    ) {
+        if !Config::AccumulateCredit::get() || purchase.native.is_some() {
+            return;
+        }
+        let value = Self::value_of(ids, purchase.weight, asset_loc, purchase.price).unwrap_or(purchase.amount);
+        let surplus = purchase.amount.saturating_sub(value.max(1));
+        let credit = self
+            .paid_assets
+            .get(asset_loc, &purchase.price)
+            .map_or(0, |paid| paid.credit);
+        let used = credit.min(surplus);
+        // `used` is at most `credit` and `surplus`, which is at most the amount, so none of the
+        // subtractions underflow.
+        purchase.amount -= used;
+        purchase.credit = Some((credit - used).saturating_add(surplus - used));
+    }
+
+    /// Determine the value of (scaled) `weight` in the asset at `location` at `price`, i.e. its
+    /// fee rounded down and before `MinimumFee` and `ChargeIncrement` are applied.
+    ///
+    /// Returns `None` on overflow.
+    fn value_of<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        weight: WeightV2,
+        location: &'a MultiLocation,
+        price: Price,
+    ) -> Option<u128> {
+        let fee = Self::fee_for(weight, Self::fee_rate(ids, location));
+        convert_fee(fee, price, Rounding::Down)
+            .and_then(|fee| Self::scale_to_decimals(ids, location, fee, Rounding::Down))
+            .and_then(|fee| Self::apply_surcharge(ids, location, fee))
+    }
+
+    /// Determine the purchase of (scaled) `weight` with the asset at `asset_loc` at `price`,
+    /// charging the `NativeFeeShare` in the native asset.
+    fn purchase_for<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        weight: WeightV2,
+        asset_loc: &'a MultiLocation,
+        price: Price,
+    ) -> Result<Purchase<Price>, XcmError> {
+        let (amount, native) = Self::charges_for(ids, weight, asset_loc, price)?;
+        Ok(Purchase {
+            weight,
+            location: asset_loc.clone(),
+            price,
+            amount,
+            fee_rate: Self::fee_rate(ids, asset_loc),
+            native: native.map(|(location, amount)| (location.clone(), amount)),
+            native_fee_rate: native.and_then(|(location, _)| Self::fee_rate(ids, location)),
+            credit: None,
+        })
+    }
+
+    /// Determine the amount of the asset at `asset_loc` at `price` and the amount of the native
+    /// asset paying the `NativeFeeShare` (if any) charged for (scaled) `weight`.
+    fn charges_for<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        weight: WeightV2,
+        asset_loc: &'a MultiLocation,
+        price: Price,
+    ) -> Result<(u128, Option<(&'a MultiLocation, u128)>), XcmError> {
+        let native_location = Self::native_share_location(ids).filter(|location| *location != asset_loc);
+        let native_weight = match native_location {
+            Some(_) => WeightV2::from_parts(
+                Config::NativeFeeShare::get().mul_floor(weight.ref_time()),
+                Config::NativeFeeShare::get().mul_floor(weight.proof_size()),
+            ),
+            None => WeightV2::zero(),
+        };
+        let amount = Self::compute_fee_with(ids, weight.saturating_sub(native_weight), asset_loc, price)?;
+        let native = match native_location {
+            Some(location) => Some((
+                location,
+                Self::compute_fee_with(ids, native_weight, location, Price::one())?,
+            )),
+            None => None,
+        };
+        Ok((amount, native))
+    }
+
+    /// Check whether `payment` contains enough of each asset to pay for `purchase`.
+    fn is_affordable(purchase: &Purchase<Price>, payment: &Assets) -> bool {
+        purchase
+            .payments()
+            .all(|(location, amount)| Self::available(payment, location) >= amount)
+    }
+
+    /// Will refund up to `weight` (both reference time and proof size) from one of the assets
+    /// tracked by the trader (see `LifoRefunds`).
+    ///
+    /// At most `MaxRefundWeightPerCall` is refunded per call in each dimension. If the refund
+    /// would be zero (e.g. because of rounding), `None` is returned and the weight is kept.
+    pub fn refund_weight_v2(&mut self, weight: WeightV2) -> Option<MultiAsset> {
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::refund_weight_v2 weight: {:?}, paid_assets: {:?}",
+            weight, self.paid_assets
+        );
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!(
+            target: "xcm::weight",
+            "MultiCurrencyTrader::refund_weight_v2",
+            weight = ?weight,
+            location = tracing::field::Empty,
+            fee = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        let (weight, refund) = self.refund_for(weight);
+        if matches!(refund, Some((_, _, 0))) {
+            return None;
+        }
+        // Will not saturate because `refund_for` caps the weight.
+        self.weight = self.weight.saturating_sub(weight);
+        refund.map(|(asset_loc, price, refund)| {
+            #[cfg(feature = "tracing")]
+            {
+                span.record("location", &tracing::field::debug(&asset_loc));
+                span.record("fee", &refund);
+            }
+            self.refunds.saturating_inc();
+            Config::FeeObserver::on_refund(&asset_loc, refund);
+            #[cfg(feature = "accounting-checks")]
+            self.refunded
+                .entry(asset_loc.clone())
+                .or_default()
+                .saturating_accrue(refund);
+            // Will not underflow because `refund_for` caps the refund.
+            self.paid_assets.refund(&asset_loc, &price, refund);
+            (Concrete(asset_loc), refund).into()
+        })
+    }
+
+    /// Will refund up to `weight` from all assets tracked by the trader, in proportion to the
+    /// amounts paid.
+    ///
+    /// Refunding all bought weight returns all tracked assets. At most `MaxRefundWeightPerCall` is
+    /// refunded per call in each dimension.
+    pub fn refund_weight_all(&mut self, weight: Weight) -> Vec<MultiAsset> {
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::refund_weight_all weight: {:?}, paid_assets: {:?}",
+            weight, self.paid_assets
+        );
+        let weight = self.refundable_weight(WeightV2::from_ref_time(weight));
+        // The fee for the weight of the trader itself is not refunded.
+        let total = self.weight.saturating_add(self.overhead);
+        let total = total.ref_time().saturating_add(total.proof_size());
+        let refunded = weight.ref_time().saturating_add(weight.proof_size());
+        // Will not saturate because `refundable_weight` caps the weight.
+        self.weight = self.weight.saturating_sub(weight);
+        if refunded.is_zero() {
+            return Vec::new();
+        }
+        let refunds: Vec<_> = self
+            .paid_assets
+            .iter()
+            .filter_map(|(key, paid)| {
+                let refund =
+                    multiply_by_rational_with_rounding(paid.amount, refunded.into(), total.into(), Rounding::Down)
+                        .unwrap_or_default();
+                (!refund.is_zero()).then(|| (key.clone(), refund))
+            })
+            .collect();
+        if !refunds.is_empty() {
+            self.refunds.saturating_inc();
+        }
+        for ((asset_loc, price), refund) in refunds.iter() {
+            Config::FeeObserver::on_refund(asset_loc, *refund);
+            // Will not underflow because `refunded <= total`.
+            self.paid_assets.refund(asset_loc, price, *refund);
+        }
+        #[cfg(feature = "accounting-checks")]
+        for (key, refund) in refunds.iter() {
+            self.refunded
+                .entry(key.0.clone())
+                .or_default()
+                .saturating_accrue(*refund);
+        }
+        refunds
+            .into_iter()
+            .map(|((asset_loc, _), refund)| (Concrete(asset_loc), refund).into())
+            .collect()
+    }
+
+    /// Get the id of the last successful buy, to refund it later via `refund_buy`.
+    pub fn last_buy_id(&self) -> Option<BuyId> {
+        Some(self.buys).filter(|buys| !buys.is_zero())
+    }
+
+    /// Will refund up to `weight` from the asset paid by the buy with `id`.
+    ///
+    /// At most the weight bought by that buy is refunded and at most the amount it paid, minus
+    /// earlier refunds of it (including refunds by the other refund methods, see
+    /// `Paid::deduct`). The native fee share (see `NativeFeeShare`) is not refunded. At
+    /// most `MaxRefundWeightPerCall` is refunded per call in each dimension.
+    ///
+    /// Returns `None` if nothing would be refunded.
+    pub fn refund_buy(&mut self, id: BuyId, weight: Weight) -> Option<MultiAsset> {
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::refund_buy id: {:?}, weight: {:?}",
+            id, weight
+        );
+        let (key, paid, index) = self.paid_assets.find_buy(id)?;
+        let record = &paid.buys[index];
+        let weight = self
+            .refundable_weight(WeightV2::from_ref_time(weight))
+            .min(record.weight);
+        let refund = Self::refund_amount(key, paid, weight)
+            .min(record.amount)
+            .min(paid.amount);
+        if refund.is_zero() {
+            return None;
+        }
+        let (asset_loc, price) = key.clone();
+        // Will not saturate because `weight` is capped by both.
+        self.weight = self.weight.saturating_sub(weight);
+        self.paid_assets.refund_buy(&asset_loc, &price, index, weight, refund);
+        self.refunds.saturating_inc();
+        Config::FeeObserver::on_refund(&asset_loc, refund);
+        #[cfg(feature = "accounting-checks")]
+        self.refunded
+            .entry(asset_loc.clone())
+            .or_default()
+            .saturating_accrue(refund);
+        Some((Concrete(asset_loc), refund).into())
+    }
+
+    /// Will refund up to `weight` from the tracked assets one after the other (in the order of
+    /// `refund_weight`), draining each asset before moving on to the next, until `weight` is
+    /// refunded or no assets are left.
+    ///
+    /// The weight covered by a drained asset is assumed to be proportional to its fee. At most
+    /// `MaxRefundWeightPerCall` is refunded per call in each dimension.
+    pub fn refund_weight_multi(&mut self, weight: Weight) -> Vec<MultiAsset> {
+        log::trace!(
+            target: "xcm::weight", "MultiCurrencyTrader::refund_weight_multi weight: {:?}, paid_assets: {:?}",
+            weight, self.paid_assets
+        );
+        let mut weight = self.refundable_weight(WeightV2::from_ref_time(weight));
+        let mut refunds = Vec::new();
+        while !weight.is_zero() {
+            let (asset_loc, price, amount, fee) = match self.next_refund_entry() {
+                Some((key, paid)) => (
+                    key.0.clone(),
+                    key.1,
+                    paid.amount,
+                    Self::refund_amount(key, paid, weight),
+                ),
+                None => break,
+            };
+            let (refund, covered) = if fee <= amount {
+                (fee, weight)
+            } else {
+                let share = Perquintill::from_rational(amount, fee);
+                let covered =
+                    WeightV2::from_parts(share.mul_floor(weight.ref_time()), share.mul_floor(weight.proof_size()));
+                (amount, covered)
+            };
+            // Will not saturate because `refundable_weight` caps the weight.
+            self.weight = self.weight.saturating_sub(covered);
+            weight = weight.saturating_sub(covered);
+            let refund = if self.sweeps_dust(self.weight) { amount } else { refund };
+            // Will not underflow because `refund <= amount`.
+            self.paid_assets.refund(&asset_loc, &price, refund);
+            if !refund.is_zero() {
+                Config::FeeObserver::on_refund(&asset_loc, refund);
+                #[cfg(feature = "accounting-checks")]
+                self.refunded
+                    .entry(asset_loc.clone())
+                    .or_default()
+                    .saturating_accrue(refund);
+                refunds.push((Concrete(asset_loc), refund).into());
+            }
+        }
+        if !refunds.is_empty() {
+            self.refunds.saturating_inc();
+        }
+        refunds
+    }
+
+    /// Will refund each of `weights` like `refund_weight`, one call after the other, combining the
+    /// refunds of consecutive calls that return the same asset into a single asset.
+    ///
+    /// Each weight is refunded by its own call, so `MaxRefundWeightPerCall` applies to each of them.
+    pub fn refund_weight_accumulated(&mut self, weights: impl IntoIterator<Item = Weight>) -> Vec<MultiAsset> {
+        let mut refunds: Vec<MultiAsset> = Vec::new();
+        for weight in weights {
+            let refund = match self.refund_weight_v2(WeightV2::from_ref_time(weight)) {
+                Some(refund) => refund,
+                None => continue,
+            };
+            let combined = match (refunds.last_mut(), &refund) {
+                (
+                    Some(MultiAsset {
+                        id: last_id,
+                        fun: Fungible(last_amount),
+                    }),
+                    MultiAsset {
+                        id,
+                        fun: Fungible(amount),
+                    },
+                ) if last_id == id => {
+                    last_amount.saturating_accrue(*amount);
+                    true
+                }
+                _ => false,
+            };
+            if !combined {
+                refunds.push(refund);
+            }
+        }
+        refunds
+    }
+
+    /// Select the asset in `payment` that pays for `weight` via `Selector` and determine its price
+    /// via the price oracle.
+    ///
+    /// If `PreferNativeAsset` is enabled and the payment contains the native asset, it is chosen
+    /// at a price of one. Otherwise, if a native fee share is configured, priced non-native assets
+    /// are preferred, as the native asset only pays for its share of the fee.
+    ///
+    /// Fails with `STALE_PRICE` if no asset could be selected and the selector rejected an asset
+    /// because of its stale price (see `MaxPriceAge`), with `AssetNotFound` otherwise.
+    fn get_asset_and_price<'a>(
+        &self,
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        weight: WeightV2,
+        payment: &'a Assets,
+    ) -> Result<(&'a MultiLocation, Price), XcmError> {
+        if let Some(native_location) = Self::preferred_native_location(ids, payment) {
+            return Ok((native_location, Price::one()));
+        }
+
+        // Memoize prices, so each asset is priced at most once per call.
+        let prices = RefCell::new(SmallVec::<[(&MultiLocation, Option<Price>); INLINE_ASSET_IDS]>::new());
+        let stale = Cell::new(false);
+        let price_of = |location: &'a MultiLocation| {
+            if let Some((_, price)) = prices.borrow().iter().find(|(l, _)| *l == location) {
+                return *price;
+            }
+            let price = Self::checked_price_of(ids, location);
+            stale.set(stale.get() || price == Err(STALE_PRICE));
+            let price = price.ok();
+            prices.borrow_mut().push((location, price));
+            price
+        };
+        let charge_of =
+            |location: &'a MultiLocation, price: Price| Self::compute_fee_with(ids, weight, location, price).ok();
+
+        let native_location = Self::native_share_location(ids);
+        let is_native = |location: &MultiLocation| native_location == Some(location);
+        let prefers_non_native = native_location.is_some()
+            && Self::concrete_assets(payment).any(|(location, _)| !is_native(location) && price_of(location).is_some());
+        let candidates =
+            Self::concrete_assets(payment).filter(|(location, _)| !(prefers_non_native && is_native(location)));
+
+        Config::Selector::select(candidates, &price_of, &charge_of)
+            .and_then(|location| Some((location, price_of(location)?)))
+            .ok_or_else(|| {
+                log::trace!(target: "xcm::weight", "MultiCurrencyTrader::buy_weight_v2 no accepted asset in payment");
+                if stale.get() {
+                    STALE_PRICE
+                } else {
+                    XcmError::AssetNotFound
+                }
+            })
+    }
+
+    /// Get the (scaled) weight bought so far, minus the weight refunded, including the weight of
+    /// the work done by the trader itself (see `WeightInfo`).
+    pub fn bought_weight(&self) -> WeightV2 {
+        self.weight.saturating_add(self.overhead)
+    }
+
+    /// Iterate over the assets paid so far (minus refunds), with the price they were paid at.
+    pub fn paid_assets(&self) -> impl Iterator<Item = (&MultiLocation, &Price, &u128)> {
+        self.paid_assets
+            .iter()
+            .map(|((location, price), paid)| (location, price, &paid.amount))
+    }
+
+    /// Create a trader that has bought `weight` (reference time) and tracks the `paid` assets, as
+    /// if they had been paid in a single buy.
+    ///
+    /// Allows testing refunds without driving `buy_weight` first.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn with_state(weight: Weight, paid: BTreeMap<(MultiLocation, Price), u128>) -> Self {
+        let mut trader = <Self as WeightTrader>::new();
+        trader.weight = WeightV2::from_ref_time(weight);
+        trader.buys = 1;
+        for ((location, price), amount) in paid {
+            let native = Config::NativeAssetLocation::get();
+            let fee_rate = Self::fee_rate(&AssetIds::new(native.as_ref()), &location);
+            trader.track_payment(location, price, amount, fee_rate);
+        }
+        trader
+    }
+
+    /// Get the number of successful `buy_weight` calls.
+    pub fn buys(&self) -> u32 {
+        self.buys
+    }
+
+    /// Get the number of refund calls that returned an asset.
+    pub fn refunds(&self) -> u32 {
+        self.refunds
+    }
+
+    /// Determine the price of the asset at `location` via the price oracle.
+    ///
+    /// Returns `None` without consulting the oracle if the asset is not accepted. A zero price is
+    /// treated as no price, as it would make weight free.
+    fn price_of<'a>(ids: &AssetIds<'a, AssetId, ConvertCurrency>, location: &'a MultiLocation) -> Option<Price> {
+        Self::checked_price_of(ids, location).ok()
+    }
+
+    /// Get the price of the asset at `location` if it is accepted as payment for weight, like
+    /// `price_of`.
+    ///
+    /// Fails with `STALE_PRICE` if the price is older than `MaxPriceAge`, with `AssetNotFound` if the
+    /// asset is not accepted or has no price.
+    fn checked_price_of<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        location: &'a MultiLocation,
+    ) -> Result<Price, XcmError> {
+        // Convert the asset only once instead of once for the check and once for the oracle.
+        let id = ids
+            .of(location)
+            .filter(Self::is_accepted_id)
+            .ok_or(XcmError::AssetNotFound)?;
+        let price = match Config::MaxPriceAge::get() {
+            // The price of the native asset does not depend on the market, so its age is not checked.
+            Some(max_age) if ids.native() != Some(location) => {
+                let (price, updated_at) = Config::TimedPrices::price_with_time(id).ok_or(XcmError::AssetNotFound)?;
+                if Config::Now::get().saturating_sub(updated_at) > max_age {
+                    log::trace!(target: "xcm::weight", "MultiCurrencyTrader price of {:?} is stale", location);
+                    return Err(STALE_PRICE);
+                }
+                price
+            }
+            _ => AcceptedCurrencyPrices::price(id).ok_or(XcmError::AssetNotFound)?,
+        };
+        if price.is_zero() {
+            return Err(XcmError::AssetNotFound);
+        }
+        Ok(price)
+    }
+
+    /// Check whether the asset at `location` is accepted as payment for weight.
+    fn is_accepted<'a>(ids: &AssetIds<'a, AssetId, ConvertCurrency>, location: &'a MultiLocation) -> bool {
+        ids.of(location).map_or(false, |id| Self::is_accepted_id(&id))
+    }
+
+    /// Check whether the asset with `id` is accepted as payment for weight.
+    fn is_accepted_id(id: &AssetId) -> bool {
+        Config::AcceptedAssets::contains(id) && !Config::DeniedAssets::contains(id)
+    }
+
+    /// Pass all revenue tracked so far to `R` instead of the `Revenue` generic.
+    ///
+    /// The tracked assets are cleared, so they will not be passed to `Revenue` on `Drop`.
+    pub fn settle_to<R: TakeRevenue>(&mut self) {
+        self.check_accounting();
+        for asset in self.take_revenue() {
+            R::take_revenue(asset);
+        }
+    }
+
+    /// Pass all revenue tracked so far to `R`, together with the `origin` that paid for it.
+    ///
+    /// The tracked assets are cleared, so they will not be passed to `Revenue` on `Drop`.
+    pub fn settle_with_origin<R: TakeRevenueFrom>(&mut self, origin: &MultiLocation) {
+        self.check_accounting();
+        for asset in self.take_revenue() {
+            R::take_revenue_from(origin, asset);
+        }
+    }
+
+    /// Pass the revenue tracked so far to `Revenue` immediately instead of waiting for `Drop`.
+    ///
+    /// Meant to be called from an executor's error handler when a program is trapped. Revenue is
+    /// only taken once: the tracked assets are cleared, so `Drop` only passes on assets bought
+    /// afterwards.
+    pub fn emit_on_trap(&mut self) {
+        self.flush_revenue();
+    }
+
+    /// Pass all revenue tracked so far to `Revenue`.
+    ///
+    /// The tracked assets are cleared and `Drop` becomes a no-op unless more weight is bought
+    /// afterwards.
+    pub fn flush_revenue(&mut self) {
+        self.check_accounting();
+        for asset in self.take_revenue() {
+            Revenue::take_revenue(asset);
+        }
+        self.flushed = true;
+    }
+
+    /// Reset the trader to its initial state, so the instance can be reused.
+    ///
+    /// **Discards all pending revenue**: it is not passed to `Revenue`, neither now nor on `Drop`.
+    /// Use `flush_revenue` first to keep it.
+    pub fn reset(&mut self) {
+        self.weight = WeightV2::zero();
+        self.overhead = WeightV2::zero();
+        self.paid_assets = Default::default();
+        self.buys = 0;
+        self.refunds = 0;
+        self.flushed = false;
+        #[cfg(feature = "accounting-checks")]
+        {
+            self.charged.clear();
+            self.refunded.clear();
+        }
+    }
+
+    /// Get the revenue that would be passed on if the trader was dropped now, without changing any
+    /// state.
+    ///
+    /// Assets paid at different prices are consolidated, so there is exactly one asset per location,
+    /// ordered by location.
+    pub fn pending_revenue(&self) -> Vec<MultiAsset> {
+        let mut revenue = BTreeMap::<MultiLocation, u128>::new();
+        for ((asset_loc, _), paid) in self.paid_assets.iter() {
+            revenue
+                .entry(asset_loc.clone())
+                .or_default()
+                .saturating_accrue(paid.amount);
+        }
+        revenue
+            .into_iter()
+            .map(|(asset_loc, amount)| (asset_loc, amount).into())
+            .collect()
+    }
+
+    /// Clear the tracked assets and return them as revenue (see `pending_revenue`).
+    fn take_revenue(&mut self) -> Vec<MultiAsset> {
+        let revenue = self.pending_revenue();
+        self.paid_assets = Default::default();
+        revenue
+    }
+
+    /// Deduct the weight of the refund itself (see `WeightInfo`) from `weight`, scale it and cap it
+    /// at the bought weight and `MaxRefundWeightPerCall`.
+    fn refundable_weight(&self, weight: WeightV2) -> WeightV2 {
+        let max_weight = Config::MaxRefundWeightPerCall::get();
+        let overhead = Config::WeightInfo::refund_weight(self.paid_assets.len() as u32);
+        Self::scale(weight.saturating_sub(overhead))
+            .min(self.weight)
+            .min(WeightV2::from_parts(max_weight, max_weight))
+    }
+
+    /// Check whether a refund leaving `remaining_weight` behind returns the whole remainder of the
+    /// refunded asset (see `SweepDustToRefund`).
+    ///
+    /// Never the case once the trader charged for its own weight (see `WeightInfo`), as the
+    /// remainder then includes that fee.
+    fn sweeps_dust(&self, remaining_weight: WeightV2) -> bool {
+        Config::SweepDustToRefund::get() && remaining_weight.is_zero() && self.overhead.is_zero()
+    }
+
+    /// Determine the (scaled) weight and the asset amount (with its paid entry) refunded for
+    /// `weight`, without changing any state.
+    fn refund_for(&self, weight: WeightV2) -> (WeightV2, Option<(MultiLocation, Price, u128)>) {
+        let weight = self.refundable_weight(weight);
+        // Will not saturate because of `min()`.
+        let remaining_weight = self.weight.saturating_sub(weight);
+        let refund = self.next_refund_entry().map(|(key, paid)| {
+            let refund = if self.sweeps_dust(remaining_weight) {
+                paid.amount
+            } else {
+                Self::refund_amount(key, paid, weight).min(paid.amount)
+            };
+            (key.0.clone(), key.1, refund)
+        });
+        (weight, refund)
+    }
+
+    /// Get the paid entry to refund from next (see `LifoRefunds`).
+    ///
+    /// Entries with a zero price are never refunded.
+    fn next_refund_entry(&self) -> Option<(&(MultiLocation, Price), &Paid<Price>)> {
+        let mut entries = self.paid_assets.iter().filter(|((_, price), _)| !price.is_zero());
+        if Config::LifoRefunds::get() {
+            entries.max_by_key(|(_, paid)| paid.sequence)
         } else {
-            None
+            entries.next()
+        }
+    }
+
+    /// Determine the amount of the entry `paid` for `key` that is worth the (scaled) `weight`, not
+    /// capped by the paid amount.
+    fn refund_amount(key: &(MultiLocation, Price), paid: &Paid<Price>, weight: WeightV2) -> u128 {
+        let (asset_loc, price) = key;
+        // Refund at the fee rate the entry was paid at.
+        let fee = Self::fee_for(weight, paid.fee_rate);
+        // Use the same checked arithmetic as `compute_fee`, so an overflow cannot refund the
+        // whole paid amount.
+        let rounding = if Config::RoundFeeUp::get() {
+            Rounding::Down
+        } else {
+            Rounding::Up
+        };
+        let native = Config::NativeAssetLocation::get();
+        let ids = AssetIds::new(native.as_ref());
+        let converted_fee = convert_fee(fee, *price, rounding)
+            .and_then(|fee| Self::scale_to_decimals(&ids, asset_loc, fee, rounding))
+            .and_then(|fee| Self::apply_surcharge(&ids, asset_loc, fee))
+            .unwrap_or_default();
+        match Self::charge_increment(&ids, asset_loc) {
+            Some(increment) => round_down_to_increment(converted_fee, increment),
+            None => converted_fee,
+        }
+    }
+
+    /// Check the internal consistency of the trader, e.g. in tests or `try-runtime` checks.
+    ///
+    /// Verifies that:
+    /// - all tracked amounts are non-zero and were paid at a non-zero price,
+    /// - all tracked amounts were paid by a buy (they have a non-zero payment sequence), and
+    /// - no weight is bought and no amounts are tracked before the first buy.
+    ///
+    /// Note that bought weight without tracked amounts is consistent, as refunds can drain an
+    /// asset before all of its weight is refunded (and vice versa because of rounding).
+    pub fn check_invariants(&self) -> Result<(), &'static str> {
+        for ((_, price), paid) in self.paid_assets.iter() {
+            if paid.amount.is_zero() {
+                return Err("Tracked amount is zero");
+            }
+            if price.is_zero() {
+                return Err("Tracked amount was paid at a zero price");
+            }
+        }
+        if self.paid_assets.iter().any(|(_, paid)| paid.sequence.is_zero()) {
+            return Err("Tracked amount was not paid by a buy");
+        }
+        if self.buys.is_zero() && (!self.bought_weight().is_zero() || !self.paid_assets.is_empty()) {
+            return Err("Weight or amounts tracked without a buy");
+        }
+        Ok(())
+    }
+
+    /// Check that no more than was charged has been refunded for any asset.
+    ///
+    /// Panics in debug builds and logs an error otherwise if the check fails. Only enabled with the
+    /// `accounting-checks` feature.
+    fn check_accounting(&self) {
+        #[cfg(feature = "accounting-checks")]
+        for (location, refunded) in self.refunded.iter() {
+            let charged = self.charged.get(location).copied().unwrap_or_default();
+            if *refunded > charged {
+                log::error!(
+                    target: "xcm::weight", "MultiCurrencyTrader refunded more than charged for {:?}: {} > {}",
+                    location, refunded, charged
+                );
+                if cfg!(debug_assertions) {
+                    panic!("MultiCurrencyTrader refunded more than charged");
+                }
+            }
+        }
+    }
+
+    /// Get the location of the native asset if it should be preferred and `payment` contains a
+    /// non-zero amount of it.
+    fn preferred_native_location<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        payment: &Assets,
+    ) -> Option<&'a MultiLocation> {
+        let native_location = ids.native().filter(|_| Config::PreferNativeAsset::get())?;
+        Self::concrete_assets(payment)
+            .any(|(location, amount)| location == native_location && !amount.is_zero())
+            .then_some(native_location)
+            .filter(|location| Self::is_accepted(ids, *location))
+    }
+
+    /// Get the location of the native asset if a share of the fee should be paid in it.
+    fn native_share_location<'a>(ids: &AssetIds<'a, AssetId, ConvertCurrency>) -> Option<&'a MultiLocation> {
+        ids.native().filter(|_| !Config::NativeFeeShare::get().is_zero())
+    }
+
+    /// Track `amount` of the asset at `location` bought at `price` (and `fee_rate`, see
+    /// `FeeRateOverride`) as paid by the current buy, returning what was paid for it so far.
+    ///
+    /// Zero amounts are not tracked and return `None`.
+    fn track_payment(
+        &mut self,
+        location: MultiLocation,
+        price: Price,
+        amount: u128,
+        fee_rate: Option<Price>,
+    ) -> Option<&mut Paid<Price>> {
+        if amount.is_zero() {
+            return None;
+        }
+        self.flushed = false;
+        #[cfg(feature = "accounting-checks")]
+        self.charged
+            .entry(location.clone())
+            .or_default()
+            .saturating_accrue(amount);
+        let paid = self.paid_assets.entry(location, price);
+        paid.amount.saturating_accrue(amount);
+        paid.sequence = self.buys;
+        paid.fee_rate = fee_rate;
+        Some(paid)
+    }
+
+    /// Iterate over the locations and amounts of the concrete fungible assets in `payment`, in the
+    /// canonical order of `Assets`.
+    fn concrete_assets(payment: &Assets) -> impl Iterator<Item = (&MultiLocation, u128)> {
+        payment.fungible.iter().filter_map(|(id, amount)| match id {
+            Concrete(location) => Some((location, *amount)),
+            Abstract(_) => None,
+        })
+    }
+
+    /// Get the amount of the asset at `location` available in `payment`.
+    ///
+    /// `Assets` merges duplicate entries of an asset (e.g. from a `MultiAssets` containing the
+    /// same location twice), so this is the combined amount of all of them.
+    fn available(payment: &Assets, location: &MultiLocation) -> u128 {
+        payment
+            .fungible
+            .iter()
+            .find_map(|(id, amount)| match id {
+                Concrete(l) if l == location => Some(*amount),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Subtract `amount` of the asset at `location` from `payment`.
+    ///
+    /// Must only be called after checking the amount is `available`.
+    fn subtract_payment(payment: &mut Assets, location: &MultiLocation, amount: u128) {
+        if amount.is_zero() {
+            return;
         }
+        payment.fungible.retain(|id, available| match id {
+            Concrete(l) if l == location => {
+                *available = available.saturating_sub(amount);
+                !available.is_zero()
+            }
+            _ => true,
+        });
+    }
+
+    /// Determine the fee in the native asset for `weight`, summing the fees for both of its
+    /// dimensions and applying `FeeMultiplier`.
+    ///
+    /// If a fee `rate` is given, it is used instead of `ConvertWeightToFee` and
+    /// `ConvertProofSizeToFee`.
+    fn fee_for(weight: WeightV2, rate: Option<Price>) -> Balance {
+        let fee = match rate {
+            Some(rate) => {
+                let weight = u128::from(weight.ref_time().saturating_add(weight.proof_size()));
+                Balance::try_from(rate.saturating_mul_int(weight)).unwrap_or_else(|_| Balance::max_value())
+            }
+            None => {
+                let ref_time_fee = ConvertWeightToFee::weight_to_fee(&WeightV2::from_ref_time(weight.ref_time()));
+                if weight.proof_size().is_zero() {
+                    ref_time_fee
+                } else {
+                    let proof_size_fee = Config::ConvertProofSizeToFee::proof_size_to_fee(weight.proof_size())
+                        .unwrap_or_else(|| {
+                            ConvertWeightToFee::weight_to_fee(&WeightV2::from_ref_time(weight.proof_size()))
+                        });
+                    ref_time_fee.saturating_add(proof_size_fee)
+                }
+            }
+        };
+        Config::FeeMultiplier::get().saturating_mul_int(fee)
+    }
+
+    /// Get the (unscaled) weight of buying weight with `payment` (see `WeightInfo`).
+    fn buy_overhead(payment: &Assets) -> WeightV2 {
+        Config::WeightInfo::buy_weight(payment.fungible.len() as u32)
+    }
+
+    /// Scale both dimensions of `weight` by `WeightScale`.
+    fn scale(weight: WeightV2) -> WeightV2 {
+        WeightV2::from_parts(
+            Config::WeightScale::convert(weight.ref_time()),
+            Config::WeightScale::convert(weight.proof_size()),
+        )
+    }
+
+    /// Determine the amount of the asset at `location` to charge for `weight` at `price`.
+    ///
+    /// This is exactly what `buy_weight` charges, including the overflow handling of
+    /// `OverflowPolicy`, so it can be used to estimate fees outside of the trader. Note that
+    /// `weight` is charged as is, i.e. it is not scaled by `WeightScale`.
+    pub fn compute_fee(weight: WeightV2, location: &MultiLocation, price: Price) -> Result<u128, XcmError> {
+        let native = Config::NativeAssetLocation::get();
+        Self::compute_fee_with(&AssetIds::new(native.as_ref()), weight, location, price)
+    }
+
+    /// Like `compute_fee`, but converting assets to their ids via `ids`.
+    fn compute_fee_with<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        weight: WeightV2,
+        location: &'a MultiLocation,
+        price: Price,
+    ) -> Result<u128, XcmError> {
+        let fee = Self::fee_for(weight, Self::fee_rate(ids, location));
+        let rounding = if Config::RoundFeeUp::get() {
+            Rounding::Up
+        } else {
+            Rounding::Down
+        };
+        let amount = convert_fee(fee, price, rounding).map_or_else(Config::OverflowPolicy::on_overflow, Ok)?;
+        let amount = Self::scale_to_decimals(ids, location, amount, rounding)
+            .map_or_else(Config::OverflowPolicy::on_overflow, Ok)?;
+        let amount =
+            Self::apply_surcharge(ids, location, amount).map_or_else(Config::OverflowPolicy::on_overflow, Ok)?;
+        let amount = amount.max(Config::MinimumFee::get().saturated_into());
+        // Weight is never free, even if its fee is truncated to zero.
+        let amount = if weight.is_zero() { amount } else { amount.max(1) };
+        let amount = match Self::charge_increment(ids, location) {
+            Some(increment) => round_up_to_increment(amount, increment),
+            None => Some(amount),
+        };
+        let amount = amount.map_or_else(Config::OverflowPolicy::on_overflow, Ok)?;
+        match Config::MaxFee::get() {
+            Some(max_fee) if amount > max_fee.saturated_into() => Err(XcmError::TooExpensive),
+            _ => Ok(amount),
+        }
+    }
+
+    /// Increase `amount` of the asset at `location` by `Surcharge`, unless it is the native asset.
+    ///
+    /// Returns `None` on overflow.
+    fn apply_surcharge(
+        ids: &AssetIds<'_, AssetId, ConvertCurrency>,
+        location: &MultiLocation,
+        amount: u128,
+    ) -> Option<u128> {
+        if ids.native() == Some(location) {
+            return Some(amount);
+        }
+        Price::one()
+            .checked_add(&Config::Surcharge::get())?
+            .checked_mul_int(amount)
+    }
+
+    /// Scale `amount` of the asset at `location` from the decimals of the native asset (at
+    /// `NativeAssetLocation`) to the decimals of the asset, as determined by `Decimals`.
+    ///
+    /// Amounts are not scaled if the decimals of either asset are unknown. Scaling down is rounded
+    /// as specified. Returns `None` on overflow.
+    fn scale_to_decimals<'a>(
+        ids: &AssetIds<'a, AssetId, ConvertCurrency>,
+        location: &'a MultiLocation,
+        amount: u128,
+        rounding: Rounding,
+    ) -> Option<u128> {
+        let decimals_of = |location| ids.of(location).and_then(Config::Decimals::convert);
+        let (native_decimals, asset_decimals) = match ids.native().and_then(decimals_of).zip(decimals_of(location)) {
+            Some(decimals) => decimals,
+            None => return Some(amount),
+        };
+        if asset_decimals >= native_decimals {
+            10u128
+                .checked_pow(u32::from(asset_decimals - native_decimals))
+                .and_then(|factor| amount.checked_mul(factor))
+        } else {
+            match 10u128.checked_pow(u32::from(native_decimals - asset_decimals)) {
+                Some(divisor) => {
+                    let rounds_up = rounding == Rounding::Up && !(amount % divisor).is_zero();
+                    Some(amount / divisor + u128::from(rounds_up))
+                }
+                // The divisor exceeds any amount.
+                None => Some(u128::from(rounding == Rounding::Up && !amount.is_zero())),
+            }
+        }
+    }
+
+    /// Determine the fee rate override for the asset at `location`, if any.
+    fn fee_rate<'a>(ids: &AssetIds<'a, AssetId, ConvertCurrency>, location: &'a MultiLocation) -> Option<Price> {
+        ids.of(location).and_then(Config::FeeRateOverride::convert)
+    }
+
+    /// Determine the increment that amounts of the asset at `location` are rounded to.
+    ///
+    /// Returns `None` if the asset is unknown or no (non-zero) increment is configured.
+    fn charge_increment<'a>(ids: &AssetIds<'a, AssetId, ConvertCurrency>, location: &'a MultiLocation) -> Option<u128> {
+        ids.of(location)
+            .and_then(Config::ChargeIncrement::convert)
+            .filter(|increment| !increment.is_zero())
     }
 }
 
 impl<
-        AssetId,
+        AssetId: Clone,
         Balance: FixedPointOperand + TryInto<u128>,
         Price: FixedPointNumber,
         ConvertWeightToFee: WeightToFee<Balance = Balance>,
         AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
         ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
         Revenue: TakeRevenue,
+        Config: TraderConfig<AssetId, Balance, Price>,
     > WeightTrader
     for MultiCurrencyTrader<
         AssetId,
@@ -107,80 +1205,208 @@ impl<
         AcceptedCurrencyPrices,
         ConvertCurrency,
         Revenue,
+        Config,
     >
 {
     fn new() -> Self {
         Self {
             weight: Default::default(),
+            overhead: Default::default(),
             paid_assets: Default::default(),
+            buys: 0,
+            refunds: 0,
+            flushed: false,
+            #[cfg(feature = "accounting-checks")]
+            charged: Default::default(),
+            #[cfg(feature = "accounting-checks")]
+            refunded: Default::default(),
             _phantom: PhantomData,
         }
     }
 
-    /// Will try to buy weight with the first asset in `payment`.
+    /// Will try to buy weight with the asset in `payment` chosen by `Selector`.
     ///
-    /// This is a reasonable strategy as the `BuyExecution` XCM instruction only passes one asset
-    /// per buy.
-    /// The fee is determined by `ConvertWeightToFee` in combination with the price determined by
-    /// `AcceptedCurrencyPrices`.
+    /// See `buy_weight_v2`, only the reference time is charged.
     fn buy_weight(&mut self, weight: Weight, payment: Assets) -> Result<Assets, XcmError> {
-        log::trace!(
-            target: "xcm::weight", "MultiCurrencyTrader::buy_weight weight: {:?}, payment: {:?}",
-            weight, payment
-        );
-        let (asset_loc, price) = self.get_asset_and_price(&payment).ok_or(XcmError::AssetNotFound)?;
-        let fee = ConvertWeightToFee::weight_to_fee(&frame_support::weights::Weight::from_ref_time(weight));
-        let converted_fee = price.checked_mul_int(fee).ok_or(XcmError::Overflow)?;
-        let amount: u128 = converted_fee.try_into().map_err(|_| XcmError::Overflow)?;
-        let required = (Concrete(asset_loc.clone()), amount).into();
-        let unused = payment.checked_sub(required).map_err(|_| XcmError::TooExpensive)?;
-        self.weight = self.weight.saturating_add(weight);
-        let key = (asset_loc, price);
-        match self.paid_assets.get_mut(&key) {
-            Some(v) => v.saturating_accrue(amount),
-            None => {
-                self.paid_assets.insert(key, amount);
-            }
-        }
-        Ok(unused)
+        self.buy_weight_v2(WeightV2::from_ref_time(weight), payment)
     }
 
-    /// Will refund up to `weight` from the first asset tracked by the trader.
+    /// Will refund up to `weight` from one of the assets tracked by the trader.
+    ///
+    /// See `refund_weight_v2`, only the reference time is refunded.
     fn refund_weight(&mut self, weight: Weight) -> Option<MultiAsset> {
-        log::trace!(
-            target: "xcm::weight", "MultiCurrencyTrader::refund_weight weight: {:?}, paid_assets: {:?}",
-            weight, self.paid_assets
-        );
-        let weight = weight.min(self.weight);
-        self.weight -= weight; // Will not underflow because of `min()` above.
-        let fee = ConvertWeightToFee::weight_to_fee(&frame_support::weights::Weight::from_ref_time(weight));
-        if let Some(((asset_loc, price), amount)) = self.paid_assets.iter_mut().next() {
-            let converted_fee: u128 = price.saturating_mul_int(fee).saturated_into();
-            let refund = converted_fee.min(*amount);
-            *amount -= refund; // Will not underflow because of `min()` above.
-
-            let refund_asset = asset_loc.clone();
-            if amount.is_zero() {
-                let key = (asset_loc.clone(), *price);
-                self.paid_assets.remove(&key);
-            }
-            Some((Concrete(refund_asset), refund).into())
-        } else {
-            None
+        self.refund_weight_v2(WeightV2::from_ref_time(weight))
+    }
+}
+
+/// A purchase of weight determined by `MultiCurrencyTrader`, not yet committed to the trader.
+struct Purchase<Price> {
+    /// The (scaled) weight bought.
+    weight: WeightV2,
+    /// The asset paying for the weight.
+    location: MultiLocation,
+    price: Price,
+    amount: u128,
+    /// The fee rate of the paying asset, see `FeeRateOverride`.
+    fee_rate: Option<Price>,
+    /// The native asset and amount paying the `NativeFeeShare`, if any.
+    native: Option<(MultiLocation, u128)>,
+    /// The fee rate of the native asset, if it pays the `NativeFeeShare`.
+    native_fee_rate: Option<Price>,
+    /// The credit of the paying asset after the purchase, if `AccumulateCredit` applies to it.
+    credit: Option<u128>,
+}
+
+/// Number of asset ids memoized inline by `AssetIds` before spilling to the heap.
+const INLINE_ASSET_IDS: usize = 4;
+
+/// Memoizes the conversion of assets to their ids via `ConvertCurrency`, so each asset is converted
+/// at most once (e.g. during a single buy) even if it is looked up repeatedly.
+///
+/// The memoized locations are borrowed, so a location is only cloned to convert it.
+struct AssetIds<'a, AssetId, ConvertCurrency> {
+    native: Option<&'a MultiLocation>,
+    ids: RefCell<SmallVec<[(&'a MultiLocation, Option<AssetId>); INLINE_ASSET_IDS]>>,
+    _phantom: PhantomData<ConvertCurrency>,
+}
+
+impl<'a, AssetId: Clone, ConvertCurrency: Convert<MultiAsset, Option<AssetId>>> AssetIds<'a, AssetId, ConvertCurrency> {
+    /// Create a memo for the assets looked up along with the native asset at `native`, if any (see
+    /// `NativeAssetLocation`).
+    fn new(native: Option<&'a MultiLocation>) -> Self {
+        Self {
+            native,
+            ids: RefCell::new(SmallVec::new()),
+            _phantom: PhantomData,
         }
     }
+
+    /// Get the location of the native asset.
+    fn native(&self) -> Option<&'a MultiLocation> {
+        self.native
+    }
+
+    /// Get the id of the asset at `location`.
+    fn of(&self, location: &'a MultiLocation) -> Option<AssetId> {
+        if let Some((_, id)) = self.ids.borrow().iter().find(|(l, _)| *l == location) {
+            return id.clone();
+        }
+        let id = ConvertCurrency::convert((Concrete(location.clone()), 0u128).into());
+        self.ids.borrow_mut().push((location, id.clone()));
+        id
+    }
+}
+
+impl<Price> Purchase<Price> {
+    /// Iterate over the (non-zero) amounts paid per asset.
+    fn payments(&self) -> impl Iterator<Item = (&MultiLocation, u128)> {
+        sp_std::iter::once((&self.location, self.amount))
+            .chain(self.native.iter().map(|(location, amount)| (location, *amount)))
+            .filter(|(_, amount)| !amount.is_zero())
+    }
+}
+
+/// Convert `fee` in the native asset to the asset with `price`, rounding as specified.
+///
+/// Returns `None` on overflow.
+fn convert_fee<Balance: FixedPointOperand + TryInto<u128>, Price: FixedPointNumber>(
+    fee: Balance,
+    price: Price,
+    rounding: Rounding,
+) -> Option<u128> {
+    match rounding {
+        Rounding::Down => price.checked_mul_int(fee).and_then(|fee| fee.try_into().ok()),
+        _ => fee.try_into().ok().and_then(|fee| {
+            multiply_by_rational_with_rounding(
+                fee,
+                price.into_inner().saturated_into(),
+                Price::DIV.saturated_into(),
+                rounding,
+            )
+        }),
+    }
+}
+
+/// Determines how `MultiCurrencyTrader` handles overflows when converting fees.
+pub trait HandleOverflow {
+    /// Get the amount to charge instead of an overflowing one, or the error to fail with.
+    fn on_overflow() -> Result<u128, XcmError>;
+}
+
+/// Fail with `XcmError::Overflow` when converting a fee overflows.
+pub struct AbortOnOverflow;
+
+impl HandleOverflow for AbortOnOverflow {
+    fn on_overflow() -> Result<u128, XcmError> {
+        Err(XcmError::Overflow)
+    }
+}
+
+/// Saturate fees that overflow when being converted, so buying weight with them fails with
+/// `XcmError::TooExpensive`.
+pub struct SaturateOnOverflow;
+
+impl HandleOverflow for SaturateOnOverflow {
+    fn on_overflow() -> Result<u128, XcmError> {
+        Ok(u128::MAX)
+    }
+}
+
+/// Observes the assets paying for weight, e.g. to update metrics or deposit events.
+pub trait Observer {
+    /// Called when `amount` of the asset at `asset` was charged for weight.
+    fn on_buy(asset: &MultiLocation, amount: u128);
+
+    /// Called when `amount` of the asset at `asset` was refunded.
+    fn on_refund(asset: &MultiLocation, amount: u128);
+
+    /// Called when `amount` of the asset at `asset` was taken as revenue, e.g. deposited by
+    /// `revenue::ToAccount`.
+    fn on_revenue(asset: &MultiLocation, amount: u128);
+}
+
+impl Observer for () {
+    fn on_buy(_asset: &MultiLocation, _amount: u128) {}
+
+    fn on_refund(_asset: &MultiLocation, _amount: u128) {}
+
+    fn on_revenue(_asset: &MultiLocation, _amount: u128) {}
+}
+
+/// Fee multiplier of one, i.e. fees are not multiplied (the default `FeeMultiplier` of
+/// `MultiCurrencyTrader`).
+pub struct UnitFeeMultiplier;
+
+impl<Price: FixedPointNumber> Get<Price> for UnitFeeMultiplier {
+    fn get() -> Price {
+        Price::one()
+    }
+}
+
+/// Round `amount` up to the next multiple of `increment`. Returns `None` on overflow.
+fn round_up_to_increment(amount: u128, increment: u128) -> Option<u128> {
+    match amount % increment {
+        0 => Some(amount),
+        remainder => amount.checked_add(increment - remainder),
+    }
+}
+
+/// Round `amount` down to the previous multiple of `increment`.
+fn round_down_to_increment(amount: u128, increment: u128) -> u128 {
+    amount - amount % increment
 }
 
 /// We implement `Drop` so that when the weight trader is dropped at the end of XCM execution, the
 /// generated revenue is stored on-chain. This is configurable via the `Revenue` generic.
 impl<
-        AssetId,
+        AssetId: Clone,
         Balance: FixedPointOperand + TryInto<u128>,
         Price: FixedPointNumber,
         ConvertWeightToFee: WeightToFee<Balance = Balance>,
         AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
         ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
         Revenue: TakeRevenue,
+        Config: TraderConfig<AssetId, Balance, Price>,
     > Drop
     for MultiCurrencyTrader<
         AssetId,
@@ -190,49 +1416,105 @@ impl<
         AcceptedCurrencyPrices,
         ConvertCurrency,
         Revenue,
+        Config,
     >
 {
     fn drop(&mut self) {
-        for ((asset_loc, _), amount) in self.paid_assets.iter() {
-            Revenue::take_revenue((asset_loc.clone(), *amount).into());
+        if !self.flushed {
+            self.flush_revenue();
         }
     }
 }
 
-/// Implements `TakeRevenue` by sending the assets to the fee receiver, using an implementor of
-/// `DepositFee`.
+/// Weight trader that buys weight with `A` and falls back to `B` if `A` does not accept the payment
+/// (fails with `AssetNotFound`, `TooExpensive` or `STALE_PRICE`).
 ///
-/// Note: Only supports concrete fungible assets.
-pub struct ToFeeReceiver<AccountId, AssetId, Balance, Price, C, D, F>(
-    PhantomData<(AccountId, AssetId, Balance, Price, C, D, F)>,
-);
-impl<
-        AccountId,
-        AssetId,
-        Balance: AtLeast32BitUnsigned,
-        Price,
-        C: Convert<MultiLocation, Option<AssetId>>,
-        D: DepositFee<AccountId, AssetId, Balance>,
-        F: TransactionMultiPaymentDataProvider<AccountId, AssetId, Price>,
-    > TakeRevenue for ToFeeReceiver<AccountId, AssetId, Balance, Price, C, D, F>
-{
-    fn take_revenue(asset: MultiAsset) {
-        match asset {
-            MultiAsset {
-                id: Concrete(loc),
-                fun: Fungibility::Fungible(amount),
-            } => {
-                C::convert(loc).and_then(|id| {
-                    let receiver = F::get_fee_receiver();
-                    D::deposit_fee(&receiver, id, amount.saturated_into::<Balance>())
-                        .map_err(|e| log::trace!(target: "xcm::take_revenue", "Could not deposit fee: {:?}", e))
-                        .ok()
-                });
+/// Refunds are taken from `A` if it took a payment, otherwise (or if `A` has nothing left to
+/// refund) from `B` if it took a payment.
+pub struct FallbackTrader<A, B> {
+    a: A,
+    b: B,
+    a_bought: bool,
+    b_bought: bool,
+}
+
+impl<A: WeightTrader, B: WeightTrader> WeightTrader for FallbackTrader<A, B> {
+    fn new() -> Self {
+        Self {
+            a: A::new(),
+            b: B::new(),
+            a_bought: false,
+            b_bought: false,
+        }
+    }
+
+    fn buy_weight(&mut self, weight: Weight, payment: Assets) -> Result<Assets, XcmError> {
+        match self.a.buy_weight(weight, payment.clone()) {
+            Ok(unused) => {
+                self.a_bought = true;
+                Ok(unused)
             }
-            _ => {
-                debug_assert!(false, "Can only accept concrete fungible tokens as revenue.");
-                log::trace!(target: "xcm::take_revenue", "Can only accept concrete fungible tokens as revenue.");
+            Err(XcmError::AssetNotFound | XcmError::TooExpensive | STALE_PRICE) => {
+                log::trace!(target: "xcm::weight", "FallbackTrader::buy_weight falling back to second trader");
+                let unused = self.b.buy_weight(weight, payment)?;
+                self.b_bought = true;
+                Ok(unused)
             }
+            Err(e) => Err(e),
         }
     }
+
+    fn refund_weight(&mut self, weight: Weight) -> Option<MultiAsset> {
+        let refund = if self.a_bought {
+            self.a.refund_weight(weight)
+        } else {
+            None
+        };
+        refund.or_else(|| {
+            if self.b_bought {
+                self.b.refund_weight(weight)
+            } else {
+                None
+            }
+        })
+    }
 }
+
+/// Implements `TakeRevenue` by sending the assets to the fee receiver provided by `F`, using an
+/// implementor of `DepositFee`.
+///
+/// See `revenue::ToAccount` for the other parameters.
+///
+/// Note: Only supports fungible assets.
+pub type ToFeeReceiver<
+    AccountId,
+    AssetId,
+    Balance,
+    Price,
+    C,
+    D,
+    F,
+    AllowedReceivers = Everything,
+    FallbackReceiver = (),
+    Hook = (),
+    ReceiverOf = (),
+    AbstractConvert = (),
+    MinDeposit = (),
+    OnFailure = (),
+    FeeObserver = (),
+> = ToAccount<
+    AccountId,
+    AssetId,
+    Balance,
+    C,
+    D,
+    FeeReceiverOf<F, AssetId, Price>,
+    AllowedReceivers,
+    FallbackReceiver,
+    Hook,
+    ReceiverOf,
+    AbstractConvert,
+    MinDeposit,
+    OnFailure,
+    FeeObserver,
+>;
@@ -0,0 +1,177 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use frame_support::traits::{Contains, Get};
+use polkadot_xcm::{latest::prelude::*, VersionedMultiLocation};
+use sp_runtime::traits::Convert;
+use sp_std::marker::PhantomData;
+
+/// Converts a location of any supported XCM version to an asset id by normalizing it to the latest
+/// `MultiLocation` before delegating to `Inner`.
+///
+/// This way `Inner` only has to match the latest representation of a location. Locations that
+/// cannot be represented in the latest version are not converted.
+pub struct VersionedLocationConverter<Inner>(PhantomData<Inner>);
+
+impl<AssetId, Inner: Convert<MultiLocation, Option<AssetId>>> Convert<VersionedMultiLocation, Option<AssetId>>
+    for VersionedLocationConverter<Inner>
+{
+    fn convert(location: VersionedMultiLocation) -> Option<AssetId> {
+        MultiLocation::try_from(location).ok().and_then(Inner::convert)
+    }
+}
+
+impl<AssetId, Inner: Convert<MultiLocation, Option<AssetId>>> Convert<MultiLocation, Option<AssetId>>
+    for VersionedLocationConverter<Inner>
+{
+    fn convert(location: MultiLocation) -> Option<AssetId> {
+        Inner::convert(location)
+    }
+}
+
+impl<AssetId, Inner: Convert<MultiLocation, Option<AssetId>>> Convert<MultiAsset, Option<AssetId>>
+    for VersionedLocationConverter<Inner>
+{
+    fn convert(asset: MultiAsset) -> Option<AssetId> {
+        match asset.id {
+            Concrete(location) => Inner::convert(location),
+            Abstract(_) => None,
+        }
+    }
+}
+
+/// Converts locations to asset ids by stripping `Prefix` from the location before delegating to
+/// `Inner`. Locations without the prefix are passed to `Inner` as is.
+///
+/// This way the same asset is recognized whether it is expressed locally (e.g.
+/// `(0, X1(GeneralKey))`) or from the perspective of a sibling (e.g.
+/// `(1, X2(Parachain, GeneralKey))` with a prefix of `(1, X1(Parachain))`).
+pub struct PrefixedLocationConverter<Prefix, Inner>(PhantomData<(Prefix, Inner)>);
+
+impl<Prefix: Get<MultiLocation>, Inner> PrefixedLocationConverter<Prefix, Inner> {
+    /// Strip `Prefix` from `location`, returning the location unchanged if it does not start with
+    /// the prefix.
+    fn strip_prefix(location: MultiLocation) -> MultiLocation {
+        let prefix = Prefix::get();
+        if location.parents != prefix.parents
+            || location.interior.len() < prefix.interior.len()
+            || prefix
+                .interior
+                .iter()
+                .zip(location.interior.iter())
+                .any(|(p, j)| p != j)
+        {
+            return location;
+        }
+        let mut interior = Junctions::Here;
+        for junction in location.interior.iter().skip(prefix.interior.len()) {
+            // Cannot fail because the rest is shorter than the original interior.
+            if interior.push(junction.clone()).is_err() {
+                return location;
+            }
+        }
+        MultiLocation::new(0, interior)
+    }
+}
+
+impl<AssetId, Prefix: Get<MultiLocation>, Inner: Convert<MultiLocation, Option<AssetId>>>
+    Convert<MultiLocation, Option<AssetId>> for PrefixedLocationConverter<Prefix, Inner>
+{
+    fn convert(location: MultiLocation) -> Option<AssetId> {
+        Inner::convert(Self::strip_prefix(location))
+    }
+}
+
+impl<AssetId, Prefix: Get<MultiLocation>, Inner: Convert<MultiLocation, Option<AssetId>>>
+    Convert<MultiAsset, Option<AssetId>> for PrefixedLocationConverter<Prefix, Inner>
+{
+    fn convert(asset: MultiAsset) -> Option<AssetId> {
+        match asset.id {
+            Concrete(location) => Inner::convert(Self::strip_prefix(location)),
+            Abstract(_) => None,
+        }
+    }
+}
+
+/// Asset ids that can be represented by a `GeneralIndex`.
+pub trait GeneralIndexId: Sized {
+    /// Get the index representing the asset id.
+    fn to_index(&self) -> u128;
+
+    /// Get the asset id represented by `index`, if any.
+    fn from_index(index: u128) -> Option<Self>;
+}
+
+macro_rules! impl_general_index_id {
+    ($($id:ty),+) => {
+        $(
+            impl GeneralIndexId for $id {
+                fn to_index(&self) -> u128 {
+                    u128::from(*self)
+                }
+
+                fn from_index(index: u128) -> Option<Self> {
+                    Self::try_from(index).ok()
+                }
+            }
+        )+
+    };
+}
+
+impl_general_index_id!(u8, u16, u32, u64, u128);
+
+/// Converts between asset ids and locations of the shape `(0, X2(PalletInstance, GeneralIndex))`,
+/// as used for the assets of `pallet-assets`.
+///
+/// `PalletId` is the index of the pallet instance holding the assets and `Store` determines which
+/// asset ids exist. Combine with `PrefixedLocationConverter` to also match the assets from the
+/// perspective of other chains.
+pub struct PalletInstanceIndexConverter<PalletId, Store>(PhantomData<(PalletId, Store)>);
+
+impl<AssetId: GeneralIndexId, PalletId: Get<u8>, Store: Contains<AssetId>> Convert<MultiLocation, Option<AssetId>>
+    for PalletInstanceIndexConverter<PalletId, Store>
+{
+    fn convert(location: MultiLocation) -> Option<AssetId> {
+        match location {
+            MultiLocation {
+                parents: 0,
+                interior: X2(PalletInstance(instance), GeneralIndex(index)),
+            } if instance == PalletId::get() => AssetId::from_index(index).filter(Store::contains),
+            _ => None,
+        }
+    }
+}
+
+impl<AssetId: GeneralIndexId, PalletId: Get<u8>, Store: Contains<AssetId>> Convert<MultiAsset, Option<AssetId>>
+    for PalletInstanceIndexConverter<PalletId, Store>
+{
+    fn convert(asset: MultiAsset) -> Option<AssetId> {
+        match asset.id {
+            Concrete(location) => <Self as Convert<MultiLocation, Option<AssetId>>>::convert(location),
+            Abstract(_) => None,
+        }
+    }
+}
+
+impl<AssetId: GeneralIndexId, PalletId: Get<u8>, Store: Contains<AssetId>> Convert<AssetId, Option<MultiLocation>>
+    for PalletInstanceIndexConverter<PalletId, Store>
+{
+    fn convert(id: AssetId) -> Option<MultiLocation> {
+        Store::contains(&id)
+            .then(|| MultiLocation::new(0, X2(PalletInstance(PalletId::get()), GeneralIndex(id.to_index()))))
+    }
+}
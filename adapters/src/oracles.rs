@@ -0,0 +1,440 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use codec::{FullCodec, FullEncode};
+use frame_support::{traits::Get, StorageMap};
+use hydradx_traits::{pools::SpotPriceProvider, NativePriceOracle};
+use polkadot_xcm::latest::prelude::*;
+use sp_runtime::{
+    traits::{BlockNumberProvider, CheckedAdd, Convert, Saturating, Zero},
+    FixedPointNumber, FixedPointOperand, PerThing, Permill,
+};
+use sp_std::{marker::PhantomData, vec::Vec};
+
+/// Minimal price source for `AssetExchange` implementations.
+///
+/// Prices are denominated in the native asset (see `NativePriceOracle`).
+pub trait AssetPriceSource<Price> {
+    /// Get the price of `asset` in terms of the native asset.
+    ///
+    /// Should return `None` if no price is available.
+    fn price(asset: &MultiAsset) -> Option<Price>;
+}
+
+/// Exposes a `NativePriceOracle` as an `AssetPriceSource`.
+///
+/// Assets are resolved via `ConvertCurrency` exactly like `MultiCurrencyTrader` does, so exchange
+/// decisions use the same prices as the weight fee computation.
+pub struct OraclePriceSource<AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices>(
+    PhantomData<(AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices)>,
+);
+
+impl<AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices> AssetPriceSource<Price>
+    for OraclePriceSource<AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices>
+where
+    ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
+    AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
+{
+    fn price(asset: &MultiAsset) -> Option<Price> {
+        ConvertCurrency::convert(asset.clone()).and_then(AcceptedCurrencyPrices::price)
+    }
+}
+
+impl<AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices>
+    OraclePriceSource<AssetId, Price, ConvertCurrency, AcceptedCurrencyPrices>
+where
+    Price: FixedPointNumber,
+    ConvertCurrency: Convert<MultiAsset, Option<AssetId>>,
+    AcceptedCurrencyPrices: NativePriceOracle<AssetId, Price>,
+{
+    /// Get the amount of `want` that corresponds to one unit of `give`.
+    ///
+    /// Returns `None` if either asset has no price or the calculation overflows.
+    pub fn exchange_rate(give: &MultiAsset, want: &MultiAsset) -> Option<Price> {
+        let give_price = <Self as AssetPriceSource<Price>>::price(give)?;
+        let want_price = <Self as AssetPriceSource<Price>>::price(want)?;
+        want_price.checked_div(&give_price)
+    }
+}
+
+/// Decides whether a price is acceptable, given a reference price from an independent source.
+pub trait PriceSanityCheck<Price> {
+    /// Returns `true` if `price` is consistent with `reference`.
+    fn is_sane(price: Price, reference: Price) -> bool;
+}
+
+/// Accepts prices that diverge from the reference price by at most `MaxDivergence` (relative to
+/// the reference price).
+pub struct MaxDivergence<Bound>(PhantomData<Bound>);
+
+impl<Price: FixedPointNumber, Bound: Get<Permill>> PriceSanityCheck<Price> for MaxDivergence<Bound> {
+    fn is_sane(price: Price, reference: Price) -> bool {
+        let divergence = if price > reference {
+            price.saturating_sub(reference)
+        } else {
+            reference.saturating_sub(price)
+        };
+        let bound = Price::saturating_from_rational(Bound::get().deconstruct(), Permill::one().deconstruct());
+        divergence <= reference.saturating_mul(bound)
+    }
+}
+
+/// Price oracle that cross-checks the prices of `Primary` against an independent `Sanity` source.
+///
+/// The price of `Primary` is only returned if `Check` accepts it against the price reported by
+/// `Sanity`. If `Sanity` has no price for the asset, the price cannot be verified and `None` is
+/// returned.
+pub struct SanityCheckedOracle<Primary, Sanity, Check>(PhantomData<(Primary, Sanity, Check)>);
+
+impl<AssetId, Price, Primary, Sanity, Check> NativePriceOracle<AssetId, Price>
+    for SanityCheckedOracle<Primary, Sanity, Check>
+where
+    AssetId: Clone,
+    Price: Copy,
+    Primary: NativePriceOracle<AssetId, Price>,
+    Sanity: NativePriceOracle<AssetId, Price>,
+    Check: PriceSanityCheck<Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        let price = Primary::price(currency.clone())?;
+        let reference = Sanity::price(currency)?;
+        if Check::is_sane(price, reference) {
+            Some(price)
+        } else {
+            log::trace!(target: "xcm::weight", "SanityCheckedOracle: price diverges from the reference price");
+            None
+        }
+    }
+}
+
+/// Price oracle that triangulates prices through an intermediate asset.
+///
+/// Returns the price of `Direct` if available. Otherwise the price is derived from `Via`, which
+/// prices assets in terms of the `Intermediate` asset, and the price of the intermediate asset
+/// reported by `Direct`:
+/// `price(asset) = price_via(asset -> intermediate) * price(intermediate -> native)`.
+pub struct TriangulatedOracle<Direct, Via, Intermediate>(PhantomData<(Direct, Via, Intermediate)>);
+
+impl<AssetId, Price, Direct, Via, Intermediate> NativePriceOracle<AssetId, Price>
+    for TriangulatedOracle<Direct, Via, Intermediate>
+where
+    AssetId: Clone,
+    Price: FixedPointNumber,
+    Direct: NativePriceOracle<AssetId, Price>,
+    Via: NativePriceOracle<AssetId, Price>,
+    Intermediate: Get<AssetId>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        Direct::price(currency.clone()).or_else(|| {
+            let via_price = Via::price(currency)?;
+            let intermediate_price = Direct::price(Intermediate::get())?;
+            via_price.checked_mul(&intermediate_price)
+        })
+    }
+}
+
+/// Price oracle that also reports how confident it is in a price, e.g. based on the liquidity of
+/// the market the price is derived from.
+pub trait ConfidentPriceOracle<AssetId, Price> {
+    /// Measure of confidence in a price, higher is more confident.
+    type Confidence: PartialOrd;
+
+    /// Get the price of `currency` in terms of the native asset together with the confidence in it.
+    ///
+    /// Should return `None` if no price is available.
+    fn price_with_confidence(currency: AssetId) -> Option<(Price, Self::Confidence)>;
+}
+
+/// Price oracle that only returns prices of `Oracle` with a confidence of at least `MinConfidence`.
+pub struct ConfidenceCheckedOracle<Oracle, MinConfidence>(PhantomData<(Oracle, MinConfidence)>);
+
+impl<AssetId, Price, Oracle, MinConfidence> NativePriceOracle<AssetId, Price>
+    for ConfidenceCheckedOracle<Oracle, MinConfidence>
+where
+    Oracle: ConfidentPriceOracle<AssetId, Price>,
+    MinConfidence: Get<Oracle::Confidence>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        let (price, confidence) = Oracle::price_with_confidence(currency)?;
+        if confidence >= MinConfidence::get() {
+            Some(price)
+        } else {
+            log::trace!(target: "xcm::weight", "ConfidenceCheckedOracle: confidence in price is too low");
+            None
+        }
+    }
+}
+
+/// Price oracle that also reports when a price was last updated, see `TraderConfig::MaxPriceAge`.
+pub trait NativePriceOracleWithTime<AssetId, Price, Moment> {
+    /// Get the price of `currency` in terms of the native asset together with the moment it was
+    /// last updated.
+    ///
+    /// Should return `None` if no price is available.
+    fn price_with_time(currency: AssetId) -> Option<(Price, Moment)>;
+}
+
+/// No prices.
+impl<AssetId, Price, Moment> NativePriceOracleWithTime<AssetId, Price, Moment> for () {
+    fn price_with_time(_currency: AssetId) -> Option<(Price, Moment)> {
+        None
+    }
+}
+
+/// A set of price oracles, implemented for tuples of up to five `NativePriceOracle`s.
+pub trait PriceSources<AssetId, Price> {
+    /// Get the prices of `currency` reported by all oracles that have one.
+    fn prices(currency: AssetId) -> Vec<Price>;
+}
+
+macro_rules! impl_price_sources_for_tuples {
+    ($($oracle:ident),+) => {
+        impl<AssetId: Clone, Price, $($oracle: NativePriceOracle<AssetId, Price>),+> PriceSources<AssetId, Price>
+            for ($($oracle,)+)
+        {
+            fn prices(currency: AssetId) -> Vec<Price> {
+                let mut prices = Vec::new();
+                $(prices.extend($oracle::price(currency.clone()));)+
+                prices
+            }
+        }
+    };
+}
+
+impl_price_sources_for_tuples!(A);
+impl_price_sources_for_tuples!(A, B);
+impl_price_sources_for_tuples!(A, B, C);
+impl_price_sources_for_tuples!(A, B, C, D);
+impl_price_sources_for_tuples!(A, B, C, D, E);
+
+/// Price oracle returning the median of the prices reported by a tuple of `Oracles`.
+///
+/// Oracles without a price are ignored. With an even number of prices, the mean of the two middle
+/// prices is returned. Returns `None` if no oracle has a price.
+pub struct MedianOracle<Oracles>(PhantomData<Oracles>);
+
+impl<AssetId, Price, Oracles> NativePriceOracle<AssetId, Price> for MedianOracle<Oracles>
+where
+    Price: FixedPointNumber,
+    Oracles: PriceSources<AssetId, Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        let mut prices = Oracles::prices(currency);
+        prices.sort();
+        let middle = prices.len() / 2;
+        if prices.len() % 2 == 1 {
+            return prices.get(middle).copied();
+        }
+        let (lower, upper) = (*prices.get(middle.checked_sub(1)?)?, prices[middle]);
+        let half = Price::saturating_from_rational(1, 2);
+        Some(lower.saturating_add(upper.saturating_sub(lower).saturating_mul(half)))
+    }
+}
+
+/// Price oracle that returns the price of `Primary` if available and falls back to `Secondary`
+/// otherwise.
+pub struct FallbackOracle<Primary, Secondary>(PhantomData<(Primary, Secondary)>);
+
+impl<AssetId, Price, Primary, Secondary> NativePriceOracle<AssetId, Price> for FallbackOracle<Primary, Secondary>
+where
+    AssetId: Clone,
+    Primary: NativePriceOracle<AssetId, Price>,
+    Secondary: NativePriceOracle<AssetId, Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        Primary::price(currency.clone()).or_else(|| Secondary::price(currency))
+    }
+}
+
+/// Price oracle deriving prices from the spot prices of an AMM (`Amm`).
+///
+/// The price of an asset is the spot price of the native asset (`NativeAssetId`) in that asset.
+/// Assets without a pool with the native asset have no price. The native asset is always priced at
+/// one.
+pub struct SpotPriceOracle<Amm, NativeAssetId>(PhantomData<(Amm, NativeAssetId)>);
+
+impl<AssetId, Price, Amm, NativeAssetId> NativePriceOracle<AssetId, Price> for SpotPriceOracle<Amm, NativeAssetId>
+where
+    AssetId: PartialEq,
+    Price: FixedPointNumber,
+    Amm: SpotPriceProvider<AssetId, Price = Price>,
+    NativeAssetId: Get<AssetId>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        let native = NativeAssetId::get();
+        if currency == native {
+            return Some(Price::one());
+        }
+        Amm::spot_price(native, currency)
+    }
+}
+
+/// Price oracle returning the reciprocal of the prices of `Inner`.
+///
+/// Useful if `Inner` reports the price of the native asset in terms of the asset instead of the
+/// other way around. Returns `None` if the price of `Inner` is zero.
+pub struct InvertedOracle<Inner>(PhantomData<Inner>);
+
+impl<AssetId, Price, Inner> NativePriceOracle<AssetId, Price> for InvertedOracle<Inner>
+where
+    Price: FixedPointNumber,
+    Inner: NativePriceOracle<AssetId, Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        Inner::price(currency).and_then(|price| price.reciprocal())
+    }
+}
+
+/// Price oracle smoothing the prices of `Inner` with an exponential moving average, to resist
+/// short-lived price manipulation.
+///
+/// The averages are persisted per asset in `Store`, together with the block (according to
+/// `BlockNumber`) they were last updated in. Querying the price does not change them: `observe`
+/// moves the average of an asset towards the price of `Inner` by `Alpha` of the difference, at
+/// most once per block, e.g. when called from `on_initialize` for the tracked assets. The first
+/// observation of an asset seeds the average with the price of `Inner`.
+///
+/// Assets that were never observed are priced by `Inner` directly.
+pub struct EmaOracle<Inner, Alpha, Store, BlockNumber>(PhantomData<(Inner, Alpha, Store, BlockNumber)>);
+
+impl<Inner, Alpha, Store, BlockNumber> EmaOracle<Inner, Alpha, Store, BlockNumber>
+where
+    Alpha: Get<Permill>,
+    BlockNumber: BlockNumberProvider,
+{
+    /// Observe the price of `Inner` for `currency` and move its average towards it, unless it was
+    /// already observed in the current block.
+    ///
+    /// Returns the updated average, or `None` (without touching the average) if `Inner` has no
+    /// price.
+    pub fn observe<AssetId, Price>(currency: AssetId) -> Option<Price>
+    where
+        AssetId: FullEncode + Clone,
+        Price: FixedPointNumber + FullCodec,
+        Inner: NativePriceOracle<AssetId, Price>,
+        Store:
+            StorageMap<AssetId, (Price, BlockNumber::BlockNumber), Query = Option<(Price, BlockNumber::BlockNumber)>>,
+    {
+        let now = BlockNumber::current_block_number();
+        let previous = Store::get(currency.clone());
+        if let Some((ema, updated)) = &previous {
+            if *updated == now {
+                return Some(*ema);
+            }
+        }
+        let price = Inner::price(currency.clone())?;
+        let ema = match previous {
+            Some((previous, _)) => {
+                let alpha = Price::saturating_from_rational(Alpha::get().deconstruct(), Permill::ACCURACY);
+                if price >= previous {
+                    previous.saturating_add(price.saturating_sub(previous).saturating_mul(alpha))
+                } else {
+                    previous.saturating_sub(previous.saturating_sub(price).saturating_mul(alpha))
+                }
+            }
+            None => price,
+        };
+        Store::insert(currency, (ema, now));
+        Some(ema)
+    }
+}
+
+impl<AssetId, Price, Inner, Alpha, Store, BlockNumber> NativePriceOracle<AssetId, Price>
+    for EmaOracle<Inner, Alpha, Store, BlockNumber>
+where
+    AssetId: FullEncode + Clone,
+    Price: FixedPointNumber + FullCodec,
+    Inner: NativePriceOracle<AssetId, Price>,
+    BlockNumber: BlockNumberProvider,
+    Store: StorageMap<AssetId, (Price, BlockNumber::BlockNumber), Query = Option<(Price, BlockNumber::BlockNumber)>>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        match Store::get(currency.clone()) {
+            Some((ema, _)) => Some(ema),
+            None => Inner::price(currency),
+        }
+    }
+}
+
+/// Price oracle returning the lower of the prices of `Spot` and `Ema` (favoring lower fees), or
+/// whichever of them is available.
+///
+/// Combining a spot price with a moving average makes the price hard to manipulate in either
+/// direction.
+pub struct MinOracle<Spot, Ema>(PhantomData<(Spot, Ema)>);
+
+impl<AssetId, Price, Spot, Ema> NativePriceOracle<AssetId, Price> for MinOracle<Spot, Ema>
+where
+    AssetId: Clone,
+    Price: Ord,
+    Spot: NativePriceOracle<AssetId, Price>,
+    Ema: NativePriceOracle<AssetId, Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        match (Spot::price(currency.clone()), Ema::price(currency)) {
+            (Some(spot), Some(ema)) => Some(spot.min(ema)),
+            (spot, ema) => spot.or(ema),
+        }
+    }
+}
+
+/// Exposes the liquidity pools backing LP share tokens.
+pub trait LiquidityPoolShares<AssetId> {
+    type Balance;
+
+    /// Get the reserves of the pool whose shares are `share_asset`, together with the total
+    /// issuance of those shares.
+    ///
+    /// Should return `None` if `share_asset` is not an LP share token.
+    fn pool_of(share_asset: &AssetId) -> Option<(Vec<(AssetId, Self::Balance)>, Self::Balance)>;
+}
+
+/// Price oracle valuing LP share tokens by the reserves of their pool.
+///
+/// The native value of a pool is the sum of its reserves priced by `Underlying`, so one share is
+/// worth that value divided by the total shares. Assets that are not LP shares according to `Pool`
+/// are priced by `Underlying` directly (which is expected to price the native asset at one).
+/// Returns `None` for empty or malformed pools: no reserves or shares, a pool without value, or a
+/// reserve asset without a price.
+pub struct LpShareOracle<Pool, Underlying>(PhantomData<(Pool, Underlying)>);
+
+impl<AssetId, Price, Pool, Underlying> NativePriceOracle<AssetId, Price> for LpShareOracle<Pool, Underlying>
+where
+    Price: FixedPointNumber,
+    Pool: LiquidityPoolShares<AssetId>,
+    Pool::Balance: FixedPointOperand + CheckedAdd,
+    Underlying: NativePriceOracle<AssetId, Price>,
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        let (reserves, shares) = match Pool::pool_of(&currency) {
+            Some(pool) => pool,
+            None => return Underlying::price(currency),
+        };
+        if reserves.is_empty() || shares.is_zero() {
+            return None;
+        }
+        let mut value = Pool::Balance::zero();
+        for (asset, reserve) in reserves {
+            let price = Underlying::price(asset).filter(|price| !price.is_zero())?;
+            value = value.checked_add(&price.reciprocal()?.checked_mul_int(reserve)?)?;
+        }
+        if value.is_zero() {
+            return None;
+        }
+        Price::checked_from_rational(shares, value)
+    }
+}
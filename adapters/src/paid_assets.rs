@@ -0,0 +1,205 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use frame_support::weights::Weight as WeightV2;
+use polkadot_xcm::latest::MultiLocation;
+use smallvec::SmallVec;
+use sp_runtime::{traits::Zero, PerThing, Perquintill};
+
+use crate::BuyId;
+
+/// Number of paid assets stored inline before spilling to the heap.
+const INLINE_PAID_ASSETS: usize = 2;
+
+type Entry<Price> = ((MultiLocation, Price), Paid<Price>);
+
+/// Amounts paid per asset location and price, ordered by key like a `BTreeMap`, together with
+/// everything else the trader tracks per paid asset.
+///
+/// Usually only one or two assets pay for the weight of a message, so up to two entries (each with
+/// the record of one buy) are stored inline and tracking them does not allocate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PaidAssets<Price>(SmallVec<[Entry<Price>; INLINE_PAID_ASSETS]>);
+
+/// What was paid for an entry of `PaidAssets`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Paid<Price> {
+    /// The amount paid, minus refunds.
+    pub(crate) amount: u128,
+    /// Number of the buy that last paid for the entry, zero if none did.
+    pub(crate) sequence: u32,
+    /// Fee rate (see `FeeRateOverride`) the entry was last paid at, if any.
+    pub(crate) fee_rate: Option<Price>,
+    /// Surplus charged beyond the value of the weight bought, see `AccumulateCredit`.
+    pub(crate) credit: u128,
+    /// What each buy paid for the entry, oldest first, for refunds via `refund_buy`.
+    pub(crate) buys: SmallVec<[BuyRecord; 1]>,
+}
+
+/// The weight bought by a buy and the amount paid for it, minus refunds via `refund_buy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BuyRecord {
+    pub(crate) id: BuyId,
+    pub(crate) weight: WeightV2,
+    pub(crate) amount: u128,
+}
+
+impl<Price> Default for PaidAssets<Price> {
+    fn default() -> Self {
+        Self(SmallVec::new())
+    }
+}
+
+impl<Price> Default for Paid<Price> {
+    fn default() -> Self {
+        Self {
+            amount: 0,
+            sequence: 0,
+            fee_rate: None,
+            credit: 0,
+            buys: SmallVec::new(),
+        }
+    }
+}
+
+impl<Price> Paid<Price> {
+    /// Deduct `refund` from the amount and from the records of the buys that paid for it (the most
+    /// recent buy first), so `refund_buy` does not refund it again.
+    ///
+    /// The weight of a record is reduced in proportion to the amount deducted from it. Records
+    /// without weight left are removed.
+    pub(crate) fn deduct(&mut self, mut refund: u128) {
+        self.amount = self.amount.saturating_sub(refund);
+        for record in self.buys.iter_mut().rev() {
+            if refund.is_zero() {
+                break;
+            }
+            if record.amount.is_zero() {
+                continue;
+            }
+            let deducted = refund.min(record.amount);
+            let share = Perquintill::from_rational(deducted, record.amount);
+            record.weight = record.weight.saturating_sub(WeightV2::from_parts(
+                share.mul_ceil(record.weight.ref_time()),
+                share.mul_ceil(record.weight.proof_size()),
+            ));
+            record.amount -= deducted; // Will not underflow because of `min()`.
+            refund -= deducted; // Will not underflow because of `min()`.
+        }
+        self.buys.retain(|record| !record.weight.is_zero());
+    }
+}
+
+impl<Price: Ord> PaidAssets<Price> {
+    fn position(&self, location: &MultiLocation, price: &Price) -> Result<usize, usize> {
+        self.0
+            .binary_search_by(|((l, p), _)| l.cmp(location).then_with(|| p.cmp(price)))
+    }
+
+    /// Get what was paid for the asset at `location` at `price`.
+    pub(crate) fn get(&self, location: &MultiLocation, price: &Price) -> Option<&Paid<Price>> {
+        let index = self.position(location, price).ok()?;
+        Some(&self.0[index].1)
+    }
+
+    /// Get what was paid for the asset at `location` at `price`, tracking it if it is not tracked
+    /// yet.
+    pub(crate) fn entry(&mut self, location: MultiLocation, price: Price) -> &mut Paid<Price> {
+        let index = match self.position(&location, &price) {
+            Ok(index) => index,
+            Err(index) => {
+                self.0.insert(index, ((location, price), Paid::default()));
+                index
+            }
+        };
+        &mut self.0[index].1
+    }
+
+    /// Deduct `refund` from what was paid for the asset at `location` at `price` (see
+    /// `Paid::deduct`), removing the entry once nothing is left of it.
+    pub(crate) fn refund(&mut self, location: &MultiLocation, price: &Price, refund: u128) {
+        if let Ok(index) = self.position(location, price) {
+            let paid = &mut self.0[index].1;
+            paid.deduct(refund);
+            if paid.amount.is_zero() {
+                self.0.remove(index);
+            }
+        }
+    }
+
+    /// Deduct `weight` and `refund` from the record at `index` of the buys that paid for the asset
+    /// at `location` at `price`, and `refund` from the amount paid for it.
+    ///
+    /// The record is removed once it has no weight left and the entry once nothing is left of it.
+    pub(crate) fn refund_buy(
+        &mut self,
+        location: &MultiLocation,
+        price: &Price,
+        index: usize,
+        weight: WeightV2,
+        refund: u128,
+    ) {
+        let position = match self.position(location, price) {
+            Ok(position) => position,
+            Err(_) => return,
+        };
+        let paid = &mut self.0[position].1;
+        paid.amount = paid.amount.saturating_sub(refund);
+        if let Some(record) = paid.buys.get_mut(index) {
+            record.weight = record.weight.saturating_sub(weight);
+            record.amount = record.amount.saturating_sub(refund);
+            if record.weight.is_zero() {
+                paid.buys.remove(index);
+            }
+        }
+        if paid.amount.is_zero() {
+            self.0.remove(position);
+        }
+    }
+}
+
+impl<Price> PaidAssets<Price> {
+    /// Iterate over the entries in key order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&(MultiLocation, Price), &Paid<Price>)> {
+        self.0.iter().map(|(key, paid)| (key, paid))
+    }
+
+    /// Find the entry paid for by the buy with `id`, with the index of its record in `Paid::buys`.
+    pub(crate) fn find_buy(&self, id: BuyId) -> Option<(&(MultiLocation, Price), &Paid<Price>, usize)> {
+        self.0.iter().find_map(|(key, paid)| {
+            let index = paid.buys.iter().position(|record| record.id == id)?;
+            Some((key, paid, index))
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<Price> IntoIterator for PaidAssets<Price> {
+    type Item = Entry<Price>;
+    type IntoIter = smallvec::IntoIter<[Entry<Price>; INLINE_PAID_ASSETS]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
@@ -0,0 +1,692 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use codec::FullCodec;
+use frame_support::{
+    traits::{Contains, Everything, Get},
+    IterableStorageMap, StorageValue,
+};
+use pallet_transaction_multi_payment::{DepositFee, TransactionMultiPaymentDataProvider};
+use polkadot_xcm::latest::prelude::*;
+use sp_runtime::{
+    traits::{AtLeast32BitUnsigned, Convert, Saturating, Zero},
+    DispatchError, DispatchResult, PerThing, Permill, SaturatedConversion,
+};
+use sp_std::{marker::PhantomData, vec::Vec};
+use xcm_builder::TakeRevenue;
+use xcm_executor::traits::Convert as ConvertLocation;
+
+use crate::Observer;
+
+/// Like `TakeRevenue`, but aware of the origin that paid the revenue.
+pub trait TakeRevenueFrom {
+    /// Take `asset` paid by `origin` as revenue.
+    fn take_revenue_from(origin: &MultiLocation, asset: MultiAsset);
+}
+
+/// Hook called when revenue is taken, e.g. to deposit an event.
+pub trait OnRevenue {
+    /// Called when `asset` was taken as revenue.
+    fn on_taken(asset: &MultiAsset);
+
+    /// Called when depositing `asset` as revenue failed with `error`.
+    fn on_deposit_failed(asset: &MultiAsset, error: DispatchError);
+}
+
+impl OnRevenue for () {
+    fn on_taken(_asset: &MultiAsset) {}
+
+    fn on_deposit_failed(_asset: &MultiAsset, _error: DispatchError) {}
+}
+
+/// Handler for revenue that could not be deposited, e.g. to buffer it in storage and retry the
+/// deposit later (like during `on_idle`).
+pub trait OnDepositFailure<AccountId, AssetId, Balance> {
+    /// Called when depositing `amount` of `asset` to `receiver` failed.
+    fn on_deposit_failure(receiver: &AccountId, asset: AssetId, amount: Balance);
+}
+
+/// Discards the revenue.
+impl<AccountId, AssetId, Balance> OnDepositFailure<AccountId, AssetId, Balance> for () {
+    fn on_deposit_failure(_receiver: &AccountId, _asset: AssetId, _amount: Balance) {}
+}
+
+/// Implements `TakeRevenueFrom` by depositing a `ReciprocalShare` of the revenue to the sovereign
+/// account of the origin (as determined by `SovereignAccountOf`), using an implementor of
+/// `DepositFee`. The rest of the revenue is passed on to `Main`.
+///
+/// If the sovereign account cannot be determined or the deposit fails, all revenue goes to `Main`.
+///
+/// Note: Only concrete fungible assets are shared, other assets are passed on to `Main` as is.
+pub struct ReciprocalRebate<AccountId, AssetId, Balance, C, D, SovereignAccountOf, ReciprocalShare, Main>(
+    PhantomData<(
+        AccountId,
+        AssetId,
+        Balance,
+        C,
+        D,
+        SovereignAccountOf,
+        ReciprocalShare,
+        Main,
+    )>,
+);
+
+impl<
+        AccountId: Clone,
+        AssetId,
+        Balance: AtLeast32BitUnsigned,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        SovereignAccountOf: ConvertLocation<MultiLocation, AccountId>,
+        ReciprocalShare: Get<Permill>,
+        Main: TakeRevenue,
+    > TakeRevenueFrom
+    for ReciprocalRebate<AccountId, AssetId, Balance, C, D, SovereignAccountOf, ReciprocalShare, Main>
+{
+    fn take_revenue_from(origin: &MultiLocation, asset: MultiAsset) {
+        let (location, amount) = match asset {
+            MultiAsset {
+                id: Concrete(ref location),
+                fun: Fungible(amount),
+            } => (location.clone(), amount),
+            _ => return Main::take_revenue(asset),
+        };
+        let share = ReciprocalShare::get().mul_floor(amount);
+        let rebated = !share.is_zero()
+            && SovereignAccountOf::convert_ref(origin)
+                .ok()
+                .zip(C::convert(location.clone()))
+                .and_then(|(sovereign, id)| {
+                    D::deposit_fee(&sovereign, id, share.saturated_into::<Balance>())
+                        .map_err(|e| log::trace!(target: "xcm::take_revenue", "Could not deposit rebate: {:?}", e))
+                        .ok()
+                })
+                .is_some();
+        let rest = if rebated { amount - share } else { amount };
+        if !rest.is_zero() {
+            Main::take_revenue((location, rest).into());
+        }
+    }
+}
+
+/// Provides the amount of revenue that should be burnt to offset inflation.
+pub trait InflationToOffset {
+    /// Get the amount of the asset at `location` that still has to be burnt to offset the
+    /// inflation of the current block.
+    fn inflation_to_offset(location: &MultiLocation) -> u128;
+
+    /// Note that `amount` of the asset at `location` was burnt to offset inflation.
+    fn note_burned(location: &MultiLocation, amount: u128);
+}
+
+/// Implements `TakeRevenue` by passing revenue to `Burner` up to the inflation still to be offset
+/// in the current block (as reported by `InflationProvider`). Any excess is passed on to
+/// `Treasury`.
+///
+/// Note: Only concrete fungible assets are burnt, other assets are passed on to `Treasury` as is.
+pub struct BalancingBurnRevenue<Burner, InflationProvider, Treasury>(
+    PhantomData<(Burner, InflationProvider, Treasury)>,
+);
+
+impl<Burner: TakeRevenue, InflationProvider: InflationToOffset, Treasury: TakeRevenue> TakeRevenue
+    for BalancingBurnRevenue<Burner, InflationProvider, Treasury>
+{
+    fn take_revenue(asset: MultiAsset) {
+        let (location, amount) = match asset {
+            MultiAsset {
+                id: Concrete(ref location),
+                fun: Fungible(amount),
+            } => (location.clone(), amount),
+            _ => return Treasury::take_revenue(asset),
+        };
+        let burnt = InflationProvider::inflation_to_offset(&location).min(amount);
+        if !burnt.is_zero() {
+            Burner::take_revenue((location.clone(), burnt).into());
+            InflationProvider::note_burned(&location, burnt);
+        }
+        let excess = amount - burnt; // Will not underflow because of `min()` above.
+        if !excess.is_zero() {
+            Treasury::take_revenue((location, excess).into());
+        }
+    }
+}
+
+/// Burns fees, reducing the total issuance of the asset.
+pub trait BurnFee<AssetId, Balance> {
+    /// Burn `amount` of `asset`.
+    fn burn_fee(asset: AssetId, amount: Balance) -> DispatchResult;
+}
+
+/// Implements `TakeRevenue` by burning the assets, using an implementor of `BurnFee`.
+///
+/// Note: Only supports concrete fungible assets.
+pub struct BurnRevenue<AssetId, Balance, C, B>(PhantomData<(AssetId, Balance, C, B)>);
+
+impl<
+        AssetId,
+        Balance: AtLeast32BitUnsigned,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        B: BurnFee<AssetId, Balance>,
+    > TakeRevenue for BurnRevenue<AssetId, Balance, C, B>
+{
+    fn take_revenue(asset: MultiAsset) {
+        match asset {
+            MultiAsset {
+                id: Concrete(loc),
+                fun: Fungible(amount),
+            } => {
+                C::convert(loc).and_then(|id| {
+                    B::burn_fee(id, amount.saturated_into::<Balance>())
+                        .map_err(|e| log::trace!(target: "xcm::take_revenue", "Could not burn fee: {:?}", e))
+                        .ok()
+                });
+            }
+            _ => {
+                log::trace!(target: "xcm::take_revenue", "Can only burn concrete fungible tokens.");
+            }
+        }
+    }
+}
+
+/// Implements `TakeRevenue` by splitting the revenue between `A` and `B`. `A` receives a `Ratio`
+/// of each asset and `B` the rest.
+///
+/// Rounding dust goes to `A`, so the split amounts always sum up to the revenue.
+///
+/// Note: Only concrete fungible assets are split, other assets are passed on to `A` as is.
+pub struct SplitRevenue<A, B, Ratio>(PhantomData<(A, B, Ratio)>);
+
+impl<A: TakeRevenue, B: TakeRevenue, Ratio: Get<Permill>> TakeRevenue for SplitRevenue<A, B, Ratio> {
+    fn take_revenue(asset: MultiAsset) {
+        let (location, amount) = match asset {
+            MultiAsset {
+                id: Concrete(ref location),
+                fun: Fungible(amount),
+            } => (location.clone(), amount),
+            _ => return A::take_revenue(asset),
+        };
+        let b_amount = Ratio::get().left_from_one().mul_floor(amount);
+        let a_amount = amount - b_amount; // Will not underflow because `b_amount <= amount`.
+        if !a_amount.is_zero() {
+            A::take_revenue((location.clone(), a_amount).into());
+        }
+        if !b_amount.is_zero() {
+            B::take_revenue((location, b_amount).into());
+        }
+    }
+}
+
+/// Implements `TakeRevenue` by accumulating revenue per asset in `Buffer` and only depositing it to
+/// the fee receiver (using an implementor of `DepositFee`) once the accumulated amount reaches
+/// `FlushThreshold`. This avoids depositing dust amounts.
+///
+/// Use `flush_all` to deposit everything buffered regardless of the threshold, e.g. in `on_idle`.
+///
+/// Assets that `C` cannot convert to an asset id are logged as a warning and passed to
+/// `Unconvertible`, which defaults to dropping them.
+///
+/// Note: Only supports concrete fungible assets.
+pub struct BufferedRevenue<AccountId, AssetId, Balance, Price, C, D, F, Buffer, FlushThreshold, Unconvertible = ()>(
+    PhantomData<(
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        C,
+        D,
+        F,
+        Buffer,
+        FlushThreshold,
+        Unconvertible,
+    )>,
+);
+
+impl<
+        AccountId,
+        AssetId: FullCodec + Clone,
+        Balance: AtLeast32BitUnsigned + FullCodec + Copy,
+        Price,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        F: TransactionMultiPaymentDataProvider<AccountId, AssetId, Price>,
+        Buffer: IterableStorageMap<AssetId, Balance, Query = Balance>,
+        FlushThreshold: Get<Balance>,
+        Unconvertible,
+    > BufferedRevenue<AccountId, AssetId, Balance, Price, C, D, F, Buffer, FlushThreshold, Unconvertible>
+{
+    /// Deposit all buffered revenue to the fee receiver.
+    ///
+    /// Revenue that fails to be deposited stays buffered.
+    pub fn flush_all() {
+        let buffered: Vec<(AssetId, Balance)> = Buffer::iter().collect();
+        for (id, amount) in buffered {
+            Self::flush(id, amount);
+        }
+    }
+
+    /// Deposit `amount` of `id` to the fee receiver and update the buffer accordingly.
+    fn flush(id: AssetId, amount: Balance) {
+        match D::deposit_fee(&F::get_fee_receiver(), id.clone(), amount) {
+            Ok(()) => Buffer::remove(id),
+            Err(e) => {
+                log::trace!(target: "xcm::take_revenue", "Could not deposit buffered fee: {:?}", e);
+                Buffer::insert(id, amount);
+            }
+        }
+    }
+}
+
+impl<
+        AccountId,
+        AssetId: FullCodec + Clone,
+        Balance: AtLeast32BitUnsigned + FullCodec + Copy,
+        Price,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        F: TransactionMultiPaymentDataProvider<AccountId, AssetId, Price>,
+        Buffer: IterableStorageMap<AssetId, Balance, Query = Balance>,
+        FlushThreshold: Get<Balance>,
+        Unconvertible: TakeRevenue,
+    > TakeRevenue
+    for BufferedRevenue<AccountId, AssetId, Balance, Price, C, D, F, Buffer, FlushThreshold, Unconvertible>
+{
+    fn take_revenue(asset: MultiAsset) {
+        match asset {
+            MultiAsset {
+                id: Concrete(loc),
+                fun: Fungibility::Fungible(amount),
+            } => match C::convert(loc.clone()) {
+                Some(id) => {
+                    let total = Buffer::get(id.clone()).saturating_add(amount.saturated_into::<Balance>());
+                    if total >= FlushThreshold::get() {
+                        Self::flush(id, total);
+                    } else {
+                        Buffer::insert(id, total);
+                    }
+                }
+                None => {
+                    log::warn!(target: "xcm::take_revenue", "Could not convert {:?} to an asset id.", loc);
+                    Unconvertible::take_revenue((loc, amount).into());
+                }
+            },
+            _ => {
+                debug_assert!(false, "Can only accept concrete fungible tokens as revenue.");
+                log::trace!(target: "xcm::take_revenue", "Can only accept concrete fungible tokens as revenue.");
+            }
+        }
+    }
+}
+
+/// Swaps assets taken as revenue, e.g. via a DEX router.
+pub trait RevenueRouter<AssetId, Balance> {
+    /// Sell exactly `amount_in` of `asset_in` for any amount of `asset_out`.
+    ///
+    /// Returns the amount of `asset_out` received.
+    fn sell(asset_in: AssetId, asset_out: AssetId, amount_in: Balance) -> Result<Balance, DispatchError>;
+}
+
+/// Implements `TakeRevenue` by swapping the revenue to the native asset (`NativeAssetId`) using
+/// `Router` and depositing the proceeds to the fee receiver, using an implementor of `DepositFee`.
+///
+/// If the swap fails, the original asset is deposited instead. Revenue in the native asset is
+/// deposited as is.
+///
+/// Note: Only supports concrete fungible assets.
+pub struct SwapToNative<AccountId, AssetId, Balance, Price, C, D, F, Router, NativeAssetId>(
+    PhantomData<(AccountId, AssetId, Balance, Price, C, D, F, Router, NativeAssetId)>,
+);
+
+impl<
+        AccountId,
+        AssetId: PartialEq + Clone,
+        Balance: AtLeast32BitUnsigned + Copy,
+        Price,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        F: TransactionMultiPaymentDataProvider<AccountId, AssetId, Price>,
+        Router: RevenueRouter<AssetId, Balance>,
+        NativeAssetId: Get<AssetId>,
+    > TakeRevenue for SwapToNative<AccountId, AssetId, Balance, Price, C, D, F, Router, NativeAssetId>
+{
+    fn take_revenue(asset: MultiAsset) {
+        match asset {
+            MultiAsset {
+                id: Concrete(loc),
+                fun: Fungibility::Fungible(amount),
+            } => {
+                if let Some(id) = C::convert(loc) {
+                    let amount = amount.saturated_into::<Balance>();
+                    let native_id = NativeAssetId::get();
+                    let (id, amount) = if id == native_id {
+                        (id, amount)
+                    } else {
+                        match Router::sell(id.clone(), native_id.clone(), amount) {
+                            Ok(native_amount) => (native_id, native_amount),
+                            Err(e) => {
+                                log::trace!(target: "xcm::take_revenue", "Could not swap fee to native: {:?}", e);
+                                (id, amount)
+                            }
+                        }
+                    };
+                    if let Err(e) = D::deposit_fee(&F::get_fee_receiver(), id, amount) {
+                        log::trace!(target: "xcm::take_revenue", "Could not deposit fee: {:?}", e);
+                    }
+                }
+            }
+            _ => {
+                debug_assert!(false, "Can only accept concrete fungible tokens as revenue.");
+                log::trace!(target: "xcm::take_revenue", "Can only accept concrete fungible tokens as revenue.");
+            }
+        }
+    }
+}
+
+/// Implements `TakeRevenue` by depositing the revenue to the account provided by `Who`, using an
+/// implementor of `DepositFee`.
+///
+/// The receiver is validated against `AllowedReceivers`. Revenue for a receiver that is not allowed
+/// is redirected to `FallbackReceiver` (or not deposited if there is none). By default all
+/// receivers are allowed.
+///
+/// `Hook` is notified about deposited revenue and failed deposits.
+///
+/// `ReceiverOf` allows routing revenue to a different receiver per asset. Assets it does not map
+/// (returns `None` for) go to `Who`. By default all assets go to `Who`.
+///
+/// Concrete assets are converted to asset ids by `C`, abstract ones by `AbstractConvert`. By default
+/// abstract assets are not deposited.
+///
+/// Amounts below `MinDeposit` are not deposited, e.g. to avoid failing deposits below the
+/// existential deposit. By default all amounts are deposited.
+///
+/// Revenue that could not be deposited is passed to `OnFailure`, e.g. to retry the deposit later.
+/// By default it is discarded.
+///
+/// `FeeObserver` is notified about deposited revenue in concrete assets. By default there is no
+/// observer.
+///
+/// Note: Only supports fungible assets.
+pub struct ToAccount<
+    AccountId,
+    AssetId,
+    Balance,
+    C,
+    D,
+    Who,
+    AllowedReceivers = Everything,
+    FallbackReceiver = (),
+    Hook = (),
+    ReceiverOf = (),
+    AbstractConvert = (),
+    MinDeposit = (),
+    OnFailure = (),
+    FeeObserver = (),
+>(
+    PhantomData<(
+        AccountId,
+        AssetId,
+        Balance,
+        C,
+        D,
+        Who,
+        AllowedReceivers,
+        FallbackReceiver,
+        Hook,
+        ReceiverOf,
+        AbstractConvert,
+        MinDeposit,
+        OnFailure,
+        FeeObserver,
+    )>,
+);
+
+impl<
+        AccountId,
+        AssetId: Clone,
+        Balance: AtLeast32BitUnsigned,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        Who: Get<AccountId>,
+        AllowedReceivers: Contains<AccountId>,
+        FallbackReceiver: Get<Option<AccountId>>,
+        Hook: OnRevenue,
+        ReceiverOf: Convert<AssetId, Option<AccountId>>,
+        AbstractConvert: Convert<Vec<u8>, Option<AssetId>>,
+        MinDeposit: Get<Balance>,
+        OnFailure: OnDepositFailure<AccountId, AssetId, Balance>,
+        FeeObserver: Observer,
+    > TakeRevenue
+    for ToAccount<
+        AccountId,
+        AssetId,
+        Balance,
+        C,
+        D,
+        Who,
+        AllowedReceivers,
+        FallbackReceiver,
+        Hook,
+        ReceiverOf,
+        AbstractConvert,
+        MinDeposit,
+        OnFailure,
+        FeeObserver,
+    >
+{
+    fn take_revenue(asset: MultiAsset) {
+        let (id, amount) = match asset {
+            MultiAsset {
+                id: Concrete(ref loc),
+                fun: Fungibility::Fungible(amount),
+            } => (C::convert(loc.clone()), amount),
+            MultiAsset {
+                id: Abstract(ref key),
+                fun: Fungibility::Fungible(amount),
+            } => (AbstractConvert::convert(key.clone()), amount),
+            _ => {
+                debug_assert!(false, "Can only accept fungible tokens as revenue.");
+                log::trace!(target: "xcm::take_revenue", "Can only accept fungible tokens as revenue.");
+                return;
+            }
+        };
+        let id = match id {
+            Some(id) => id,
+            None => {
+                log::trace!(target: "xcm::take_revenue", "Could not convert asset {:?} to an asset id.", asset.id);
+                return;
+            }
+        };
+        let amount = amount.saturated_into::<Balance>();
+        if amount < MinDeposit::get() {
+            log::trace!(target: "xcm::take_revenue", "Skipping deposit of {:?} below the minimum.", asset);
+            return;
+        }
+        let receiver = match ReceiverOf::convert(id.clone())
+            .or_else(|| Some(Who::get()))
+            .filter(AllowedReceivers::contains)
+            .or_else(|| {
+                log::trace!(target: "xcm::take_revenue", "Fee receiver is not allowed, using fallback.");
+                FallbackReceiver::get()
+            }) {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        match D::deposit_fee(&receiver, id.clone(), amount.clone()) {
+            Ok(()) => {
+                if let MultiAsset {
+                    id: Concrete(ref location),
+                    fun: Fungible(amount),
+                } = asset
+                {
+                    FeeObserver::on_revenue(location, amount);
+                }
+                Hook::on_taken(&asset);
+            }
+            Err(e) => {
+                log::trace!(target: "xcm::take_revenue", "Could not deposit fee: {:?}", e);
+                Hook::on_deposit_failed(&asset, e);
+                OnFailure::on_deposit_failure(&receiver, id, amount);
+            }
+        }
+    }
+}
+
+/// Provides the fee receiver of a `TransactionMultiPaymentDataProvider` as a `Get`, e.g. for
+/// `ToAccount`.
+pub struct FeeReceiverOf<F, AssetId, Price>(PhantomData<(F, AssetId, Price)>);
+
+impl<AccountId, AssetId, Price, F: TransactionMultiPaymentDataProvider<AccountId, AssetId, Price>> Get<AccountId>
+    for FeeReceiverOf<F, AssetId, Price>
+{
+    fn get() -> AccountId {
+        F::get_fee_receiver()
+    }
+}
+
+/// Implements `TakeRevenue` by depositing each revenue to the next of the `Accounts` in rotation,
+/// using an implementor of `DepositFee`.
+///
+/// The position in the rotation is persisted in `State`. Revenue is not deposited if there are no
+/// `Accounts`.
+///
+/// Note: Only supports concrete fungible assets.
+pub struct RoundRobinReceiver<AccountId, AssetId, Balance, C, D, Accounts, State>(
+    PhantomData<(AccountId, AssetId, Balance, C, D, Accounts, State)>,
+);
+
+impl<
+        AccountId,
+        AssetId,
+        Balance: AtLeast32BitUnsigned,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+        Accounts: Get<Vec<AccountId>>,
+        State: StorageValue<u32, Query = u32>,
+    > TakeRevenue for RoundRobinReceiver<AccountId, AssetId, Balance, C, D, Accounts, State>
+{
+    fn take_revenue(asset: MultiAsset) {
+        match asset {
+            MultiAsset {
+                id: Concrete(loc),
+                fun: Fungible(amount),
+            } => {
+                let id = match C::convert(loc) {
+                    Some(id) => id,
+                    None => return,
+                };
+                let accounts = Accounts::get();
+                if accounts.is_empty() {
+                    log::trace!(target: "xcm::take_revenue", "No accounts to deposit fee to.");
+                    return;
+                }
+                // The accounts might have changed since the last deposit.
+                let index = State::get() as usize % accounts.len();
+                State::put(((index + 1) % accounts.len()) as u32);
+                if let Err(e) = D::deposit_fee(&accounts[index], id, amount.saturated_into::<Balance>()) {
+                    log::trace!(target: "xcm::take_revenue", "Could not deposit fee: {:?}", e);
+                }
+            }
+            _ => {
+                debug_assert!(false, "Can only accept concrete fungible tokens as revenue.");
+                log::trace!(target: "xcm::take_revenue", "Can only accept concrete fungible tokens as revenue.");
+            }
+        }
+    }
+}
+
+/// Provides the account that referred the revenue currently being taken, if any.
+pub trait ReferrerLookup {
+    type AccountId;
+
+    /// Get the referrer of the current revenue.
+    fn referrer() -> Option<Self::AccountId>;
+}
+
+/// Deposits revenue to a given account.
+pub trait DepositRevenueTo<AccountId> {
+    /// Deposit `asset` to `who`.
+    fn deposit_revenue_to(who: &AccountId, asset: MultiAsset) -> DispatchResult;
+}
+
+/// Implements `DepositRevenueTo` using an implementor of `DepositFee`.
+///
+/// Note: Only supports concrete fungible assets.
+pub struct DepositFeeTo<AssetId, Balance, C, D>(PhantomData<(AssetId, Balance, C, D)>);
+
+impl<
+        AccountId,
+        AssetId,
+        Balance: AtLeast32BitUnsigned,
+        C: Convert<MultiLocation, Option<AssetId>>,
+        D: DepositFee<AccountId, AssetId, Balance>,
+    > DepositRevenueTo<AccountId> for DepositFeeTo<AssetId, Balance, C, D>
+{
+    fn deposit_revenue_to(who: &AccountId, asset: MultiAsset) -> DispatchResult {
+        match asset {
+            MultiAsset {
+                id: Concrete(loc),
+                fun: Fungible(amount),
+            } => {
+                let id = C::convert(loc).ok_or(DispatchError::Other("Unknown asset"))?;
+                D::deposit_fee(who, id, amount.saturated_into::<Balance>())
+            }
+            _ => Err(DispatchError::Other("Can only deposit concrete fungible tokens")),
+        }
+    }
+}
+
+/// Implements `TakeRevenue` by depositing a `Share` of the revenue to the referrer provided by
+/// `Lookup`, using `Referrer`. The rest of the revenue is passed on to `Main`.
+///
+/// If there is no referrer or the deposit fails, all revenue goes to `Main`. Rounding dust goes to
+/// `Main`, so the split amounts always sum up to the revenue.
+///
+/// Note: Only concrete fungible assets are shared, other assets are passed on to `Main` as is.
+pub struct ReferralSplit<Main, Referrer, Share, Lookup>(PhantomData<(Main, Referrer, Share, Lookup)>);
+
+impl<Main, Referrer, Share, Lookup> TakeRevenue for ReferralSplit<Main, Referrer, Share, Lookup>
+where
+    Main: TakeRevenue,
+    Referrer: DepositRevenueTo<Lookup::AccountId>,
+    Share: Get<Permill>,
+    Lookup: ReferrerLookup,
+{
+    fn take_revenue(asset: MultiAsset) {
+        let (location, amount) = match asset {
+            MultiAsset {
+                id: Concrete(ref location),
+                fun: Fungible(amount),
+            } => (location.clone(), amount),
+            _ => return Main::take_revenue(asset),
+        };
+        let share = Share::get().mul_floor(amount);
+        let referred = !share.is_zero()
+            && Lookup::referrer()
+                .and_then(|referrer| {
+                    Referrer::deposit_revenue_to(&referrer, (location.clone(), share).into())
+                        .map_err(
+                            |e| log::trace!(target: "xcm::take_revenue", "Could not deposit referral share: {:?}", e),
+                        )
+                        .ok()
+                })
+                .is_some();
+        let rest = if referred { amount - share } else { amount };
+        if !rest.is_zero() {
+            Main::take_revenue((location, rest).into());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use polkadot_xcm::latest::prelude::*;
+use sp_runtime::{traits::Zero, FixedPointNumber};
+
+/// Decides which asset in a payment pays for weight.
+///
+/// Selection is independent of the order in which assets appear in an XCM message: `Assets` is
+/// canonical and always iterates fungible assets in ascending `AssetId` order, no matter in which
+/// order they were added. Selectors must only depend on this canonical order.
+pub trait AssetSelector<Price> {
+    /// Select the asset among `candidates` that should be charged.
+    ///
+    /// `candidates` are the locations and amounts of the assets in the payment, in the canonical
+    /// order. `price_of` returns the price of an asset (`None` if it cannot pay for weight) and
+    /// `charge_of` the amount of an asset that would be charged at the given price (`None` if it
+    /// cannot be determined).
+    ///
+    /// Returns the location of the selected asset, or `None` if no candidate can be used.
+    fn select<'a>(
+        candidates: impl Iterator<Item = (&'a MultiLocation, u128)>,
+        price_of: impl Fn(&'a MultiLocation) -> Option<Price>,
+        charge_of: impl Fn(&'a MultiLocation, Price) -> Option<u128>,
+    ) -> Option<&'a MultiLocation>;
+}
+
+/// Selects the first asset with a non-zero amount that has a price (the default `Selector` of
+/// `MultiCurrencyTrader`).
+///
+/// Assets without a price are skipped, so a payment can carry a backup asset in case the primary
+/// one cannot be priced.
+pub struct FirstAssetSelector;
+
+impl<Price> AssetSelector<Price> for FirstAssetSelector {
+    fn select<'a>(
+        mut candidates: impl Iterator<Item = (&'a MultiLocation, u128)>,
+        price_of: impl Fn(&'a MultiLocation) -> Option<Price>,
+        _charge_of: impl Fn(&'a MultiLocation, Price) -> Option<u128>,
+    ) -> Option<&'a MultiLocation> {
+        candidates
+            .find(|(location, available)| !available.is_zero() && price_of(*location).is_some())
+            .map(|(location, _)| location)
+    }
+}
+
+/// Selects the priced asset whose charged amount has the lowest value in the native asset.
+///
+/// Only assets that can cover the fee are considered and ties are broken by `MultiLocation`
+/// ordering. If no asset can cover the fee, the first priced asset is selected.
+pub struct CheapestAssetSelector;
+
+impl<Price: FixedPointNumber> AssetSelector<Price> for CheapestAssetSelector {
+    fn select<'a>(
+        candidates: impl Iterator<Item = (&'a MultiLocation, u128)>,
+        price_of: impl Fn(&'a MultiLocation) -> Option<Price>,
+        charge_of: impl Fn(&'a MultiLocation, Price) -> Option<u128>,
+    ) -> Option<&'a MultiLocation> {
+        let mut first_priced = None;
+        let mut cheapest: Option<(&MultiLocation, Price, u128)> = None;
+        for (location, available) in candidates.filter(|(_, available)| !available.is_zero()) {
+            let price = match price_of(location) {
+                Some(price) => price,
+                None => continue,
+            };
+            first_priced.get_or_insert(location);
+            let amount = match charge_of(location, price).filter(|amount| *amount <= available) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            // compare `amount / price` with `cheapest_amount / cheapest_price`, keeping the earlier
+            // (i.e. lower) location on ties
+            let is_cheaper = cheapest.map_or(true, |(_, cheapest_price, cheapest_amount)| {
+                cheapest_price.saturating_mul_int(amount) < price.saturating_mul_int(cheapest_amount)
+            });
+            if is_cheaper {
+                cheapest = Some((location, price, amount));
+            }
+        }
+        cheapest.map(|(location, ..)| location).or(first_priced)
+    }
+}
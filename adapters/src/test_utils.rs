@@ -0,0 +1,233 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mocks for testing the wiring of `MultiCurrencyTrader`, `TakeRevenue` and `DepositFee`.
+//!
+//! Only available with the `test-utils` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use frame_support::{parameter_types, traits::Contains, weights::IdentityFee};
+//! use hydradx_adapters::test_utils::{ExpectRevenue, MockConvert, MockOracle};
+//! use hydradx_adapters::MultiCurrencyTrader;
+//! use polkadot_xcm::latest::prelude::*;
+//! use sp_runtime::{traits::Convert, FixedPointNumber, FixedU128};
+//! use xcm_executor::traits::WeightTrader;
+//!
+//! parameter_types! {
+//!     pub Prices: Vec<(u32, FixedU128)> = vec![
+//!         (0, FixedU128::saturating_from_integer(1)),
+//!         (1, FixedU128::saturating_from_integer(2)),
+//!     ];
+//! }
+//!
+//! pub struct KnownAssets;
+//! impl Contains<u32> for KnownAssets {
+//!     fn contains(id: &u32) -> bool {
+//!         *id <= 1
+//!     }
+//! }
+//!
+//! type Trader = MultiCurrencyTrader<
+//!     u32,
+//!     u128,
+//!     FixedU128,
+//!     IdentityFee<u128>,
+//!     MockOracle<u32, FixedU128, Prices>,
+//!     MockConvert<u32, KnownAssets>,
+//!     ExpectRevenue,
+//! >;
+//!
+//! let location = MockConvert::<u32, KnownAssets>::convert(1).unwrap();
+//! let payment: MultiAsset = (Concrete(location), 2_000).into();
+//! {
+//!     let mut trader = Trader::new();
+//!     assert!(trader.buy_weight(1_000, payment.clone().into()).unwrap().is_empty());
+//! }
+//! ExpectRevenue::register_expected_asset(payment);
+//! ExpectRevenue::expect_revenue();
+//! ```
+
+use codec::{Decode, Encode};
+use frame_support::traits::{Contains, Get};
+use hydradx_traits::NativePriceOracle;
+use pallet_transaction_multi_payment::DepositFee;
+use polkadot_xcm::latest::prelude::*;
+use sp_runtime::{traits::Convert, DispatchResult};
+use sp_std::{cell::RefCell, collections::btree_set::BTreeSet, fmt::Debug, marker::PhantomData, vec::Vec};
+use xcm_builder::TakeRevenue;
+
+/// Mock price oracle which returns the prices listed by `Prices`.
+pub struct MockOracle<AssetId, Price, Prices>(PhantomData<(AssetId, Price, Prices)>);
+
+impl<AssetId: PartialEq, Price: Copy, Prices: Get<Vec<(AssetId, Price)>>> NativePriceOracle<AssetId, Price>
+    for MockOracle<AssetId, Price, Prices>
+{
+    fn price(currency: AssetId) -> Option<Price> {
+        Prices::get()
+            .into_iter()
+            .find_map(|(id, price)| (id == currency).then_some(price))
+    }
+}
+
+/// Asset ids supported by `MockConvert`.
+///
+/// Keeps the conversion from asset ids to locations from overlapping with the conversion from
+/// locations to asset ids.
+pub trait MockAssetId: Encode + Decode {}
+
+impl MockAssetId for u8 {}
+impl MockAssetId for u16 {}
+impl MockAssetId for u32 {}
+impl MockAssetId for u64 {}
+impl MockAssetId for u128 {}
+
+/// Mock converter which maps the assets in `Known` to (and from) locations with their encoded id
+/// as `GeneralKey`.
+pub struct MockConvert<AssetId, Known>(PhantomData<(AssetId, Known)>);
+
+impl<AssetId: MockAssetId, Known: Contains<AssetId>> Convert<AssetId, Option<MultiLocation>>
+    for MockConvert<AssetId, Known>
+{
+    fn convert(id: AssetId) -> Option<MultiLocation> {
+        if !Known::contains(&id) {
+            return None;
+        }
+        let key = id.encode().try_into().ok()?;
+        Some(MultiLocation::new(0, X1(GeneralKey(key))))
+    }
+}
+
+impl<AssetId: Decode, Known: Contains<AssetId>> Convert<MultiLocation, Option<AssetId>>
+    for MockConvert<AssetId, Known>
+{
+    fn convert(location: MultiLocation) -> Option<AssetId> {
+        match location {
+            MultiLocation {
+                parents: 0,
+                interior: X1(GeneralKey(key)),
+            } => AssetId::decode(&mut &key[..]).ok().filter(Known::contains),
+            _ => None,
+        }
+    }
+}
+
+impl<AssetId: Decode, Known: Contains<AssetId>> Convert<MultiAsset, Option<AssetId>> for MockConvert<AssetId, Known> {
+    fn convert(asset: MultiAsset) -> Option<AssetId> {
+        if let MultiAsset {
+            id: Concrete(location), ..
+        } = asset
+        {
+            Self::convert(location)
+        } else {
+            None
+        }
+    }
+}
+
+thread_local! {
+    pub static TAKEN_REVENUE: RefCell<BTreeSet<MultiAsset>> = RefCell::new(BTreeSet::new());
+    pub static EXPECTED_REVENUE: RefCell<BTreeSet<MultiAsset>> = RefCell::new(BTreeSet::new());
+}
+
+/// Mock `TakeRevenue` which records the taken revenue, so it can be checked against expectations.
+pub struct ExpectRevenue;
+impl ExpectRevenue {
+    /// Register an asset to be expected.
+    pub fn register_expected_asset(asset: MultiAsset) {
+        EXPECTED_REVENUE.with(|e| e.borrow_mut().insert(asset));
+    }
+
+    /// Check the taken revenue contains all expected assets.
+    ///
+    /// Note: Will not notice if extra assets were taken (that were not expected).
+    pub fn expect_revenue() {
+        EXPECTED_REVENUE.with(|e| {
+            let expected = e.borrow();
+            for asset in expected.iter() {
+                assert!(TAKEN_REVENUE.with(|t| t.borrow().contains(asset)));
+            }
+        });
+    }
+
+    /// Expect there to be no tracked revenue.
+    pub fn expect_no_revenue() {
+        assert!(
+            TAKEN_REVENUE.with(|t| t.borrow().is_empty()),
+            "There should be no revenue taken."
+        );
+    }
+
+    /// Reset the global mutable state.
+    pub fn reset() {
+        TAKEN_REVENUE.with(|t| *t.borrow_mut() = BTreeSet::new());
+        EXPECTED_REVENUE.with(|e| *e.borrow_mut() = BTreeSet::new());
+    }
+}
+
+impl TakeRevenue for ExpectRevenue {
+    fn take_revenue(asset: MultiAsset) {
+        TAKEN_REVENUE.with(|t| t.borrow_mut().insert(asset));
+    }
+}
+
+thread_local! {
+    /// The expected deposits, SCALE encoded as `(AccountId, AssetId, Balance)`.
+    pub static EXPECTED_DEPOSITS: RefCell<BTreeSet<Vec<u8>>> = RefCell::new(BTreeSet::new());
+}
+
+/// Mock `DepositFee` which only accepts deposits that were registered as expected.
+pub struct ExpectDeposit<AccountId, AssetId, Balance>(PhantomData<(AccountId, AssetId, Balance)>);
+
+impl<AccountId: Encode, AssetId: Encode, Balance: Encode> ExpectDeposit<AccountId, AssetId, Balance> {
+    /// Register an asset to be expected. The `DepositFee` implementation will panic if it receives
+    /// an unexpected asset.
+    pub fn register_expected_fee(who: AccountId, asset: AssetId, amount: Balance) {
+        EXPECTED_DEPOSITS.with(|e| e.borrow_mut().insert((who, asset, amount).encode()));
+    }
+
+    /// Check that all expected fees have been deposited.
+    pub fn expect_no_remaining_fees() {
+        EXPECTED_DEPOSITS.with(|remaining| {
+            assert!(
+                remaining.borrow().is_empty(),
+                "There should be no expected fees remaining. Remaining: {:?}",
+                remaining
+            );
+        });
+    }
+
+    /// Reset the global mutable state.
+    pub fn reset() {
+        EXPECTED_DEPOSITS.with(|e| *e.borrow_mut() = BTreeSet::new());
+    }
+}
+
+impl<AccountId: Encode + Debug, AssetId: Encode + Debug, Balance: Encode + Debug>
+    DepositFee<AccountId, AssetId, Balance> for ExpectDeposit<AccountId, AssetId, Balance>
+{
+    fn deposit_fee(who: &AccountId, asset: AssetId, amount: Balance) -> DispatchResult {
+        log::trace!("Depositing {:?} of {:?} to {:?}", amount, asset, who);
+        assert!(
+            EXPECTED_DEPOSITS.with(|e| e.borrow_mut().remove(&(who, &asset, &amount).encode())),
+            "Unexpected combination of receiver and fee {:?} deposited that was not expected.",
+            (who, asset, amount)
+        );
+        Ok(())
+    }
+}
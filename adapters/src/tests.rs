@@ -16,8 +16,13 @@
 // limitations under the License.
 
 use super::*;
-use codec::{Decode, Encode};
+use crate::selector::CheapestAssetSelector;
+use crate::test_utils::{self, ExpectRevenue, TAKEN_REVENUE};
+use crate::trader_config;
+use codec::Encode;
+use frame_support::parameter_types;
 use frame_support::weights::IdentityFee;
+use pallet_transaction_multi_payment::{DepositFee, TransactionMultiPaymentDataProvider};
 use sp_runtime::{traits::One, DispatchError, DispatchResult, FixedU128};
 use sp_std::cell::RefCell;
 use sp_std::collections::btree_set::BTreeSet;
@@ -32,141 +37,27 @@ const TEST_ASSET_ID: AssetId = 123;
 const CHEAP_ASSET_ID: AssetId = 420;
 const OVERFLOW_ASSET_ID: AssetId = 1_000;
 
-/// Mock price oracle which returns prices for the hard-coded assets.
-struct MockOracle;
-impl NativePriceOracle<AssetId, Price> for MockOracle {
-    fn price(currency: AssetId) -> Option<Price> {
-        match currency {
-            CORE_ASSET_ID => Some(Price::one()),
-            TEST_ASSET_ID => Some(Price::from_float(0.5)),
-            CHEAP_ASSET_ID => Some(Price::saturating_from_integer(4)),
-            OVERFLOW_ASSET_ID => Some(Price::saturating_from_integer(2_147_483_647)),
-            _ => None,
-        }
-    }
-}
-
-struct MockConvert;
-impl Convert<AssetId, Option<MultiLocation>> for MockConvert {
-    fn convert(id: AssetId) -> Option<MultiLocation> {
-        match id {
-            CORE_ASSET_ID | TEST_ASSET_ID | CHEAP_ASSET_ID | OVERFLOW_ASSET_ID => {
-                Some(MultiLocation::new(0, X1(GeneralKey(id.encode().try_into().unwrap()))))
-            }
-            _ => None,
-        }
-    }
-}
-
-impl Convert<MultiLocation, Option<AssetId>> for MockConvert {
-    fn convert(location: MultiLocation) -> Option<AssetId> {
-        match location {
-            MultiLocation {
-                parents: 0,
-                interior: X1(GeneralKey(key)),
-            } => {
-                if let Ok(currency_id) = AssetId::decode(&mut &key[..]) {
-                    // we currently have only one native asset
-                    match currency_id {
-                        CORE_ASSET_ID | TEST_ASSET_ID | CHEAP_ASSET_ID | OVERFLOW_ASSET_ID => Some(currency_id),
-                        _ => None,
-                    }
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
-    }
-}
-
-impl Convert<MultiAsset, Option<AssetId>> for MockConvert {
-    fn convert(asset: MultiAsset) -> Option<AssetId> {
-        if let MultiAsset {
-            id: Concrete(location), ..
-        } = asset
-        {
-            Self::convert(location)
-        } else {
-            None
-        }
-    }
-}
-
-thread_local! {
-    pub static TAKEN_REVENUE: RefCell<BTreeSet<MultiAsset>> = RefCell::new(BTreeSet::new());
-    pub static EXPECTED_REVENUE: RefCell<BTreeSet<MultiAsset>> = RefCell::new(BTreeSet::new());
-}
-
-struct ExpectRevenue;
-impl ExpectRevenue {
-    /// Register an asset to be expected.
-    fn register_expected_asset(asset: MultiAsset) {
-        EXPECTED_REVENUE.with(|e| e.borrow_mut().insert(asset));
-    }
-
-    /// Check the taken revenue contains all expected assets.
-    ///
-    /// Note: Will not notice if extra assets were taken (that were not expected).
-    fn expect_revenue() {
-        EXPECTED_REVENUE.with(|e| {
-            let expected = e.borrow();
-            for asset in expected.iter() {
-                assert!(TAKEN_REVENUE.with(|t| t.borrow().contains(dbg!(asset))));
-            }
-        });
-    }
-
-    /// Expect there to be no tracked revenue.
-    fn expect_no_revenue() {
-        assert!(
-            TAKEN_REVENUE.with(|t| t.borrow().is_empty()),
-            "There should be no revenue taken."
-        );
-    }
-
-    /// Reset the global mutable state.
-    fn reset() {
-        TAKEN_REVENUE.with(|t| *t.borrow_mut() = BTreeSet::new());
-        EXPECTED_REVENUE.with(|e| *e.borrow_mut() = BTreeSet::new());
-    }
-}
-
-impl TakeRevenue for ExpectRevenue {
-    fn take_revenue(asset: MultiAsset) {
-        TAKEN_REVENUE.with(|t| t.borrow_mut().insert(asset));
-    }
-}
-
-thread_local! {
-    pub static EXPECTED_DEPOSITS: RefCell<BTreeSet<(AccountId, AssetId, Balance)>> = RefCell::new(BTreeSet::new());
+parameter_types! {
+    pub MockPrices: Vec<(AssetId, Price)> = vec![
+        (CORE_ASSET_ID, Price::one()),
+        (TEST_ASSET_ID, Price::from_float(0.5)),
+        (CHEAP_ASSET_ID, Price::saturating_from_integer(4)),
+        (OVERFLOW_ASSET_ID, Price::saturating_from_integer(2_147_483_647)),
+    ];
 }
 
-struct ExpectDeposit;
-impl ExpectDeposit {
-    /// Register an asset to be expected. The `DepositFee` implementation will panic if it receives
-    /// an unexpected asset.
-    fn register_expected_fee(who: AccountId, asset: AssetId, amount: Balance) {
-        EXPECTED_DEPOSITS.with(|e| e.borrow_mut().insert((who, asset, amount)));
-    }
-
-    /// Reset the global mutable state.
-    fn reset() {
-        EXPECTED_DEPOSITS.with(|e| *e.borrow_mut() = BTreeSet::new());
+/// The assets known to `MockConvert`.
+struct MockAssets;
+impl Contains<AssetId> for MockAssets {
+    fn contains(id: &AssetId) -> bool {
+        matches!(*id, CORE_ASSET_ID | TEST_ASSET_ID | CHEAP_ASSET_ID | OVERFLOW_ASSET_ID)
     }
 }
 
-impl DepositFee<AccountId, AssetId, Balance> for ExpectDeposit {
-    fn deposit_fee(who: &AccountId, asset: AssetId, amount: Balance) -> DispatchResult {
-        log::trace!("Depositing {} of {} to {}", amount, asset, who);
-        assert!(
-            EXPECTED_DEPOSITS.with(|e| e.borrow_mut().remove(&(*who, asset, amount))),
-            "Unexpected combination of receiver and fee {:?} deposited that was not expected.",
-            (*who, asset, amount)
-        );
-        Ok(())
-    }
-}
+/// Mock price oracle which returns prices for the hard-coded assets.
+type MockOracle = test_utils::MockOracle<AssetId, Price, MockPrices>;
+type MockConvert = test_utils::MockConvert<AssetId, MockAssets>;
+type ExpectDeposit = test_utils::ExpectDeposit<AccountId, AssetId, Balance>;
 
 #[test]
 fn can_buy_weight() {
@@ -295,6 +186,45 @@ fn overflow_errors() {
     assert_eq!(res, Err(XcmError::Overflow));
 }
 
+#[test]
+fn compute_fee_matches_known_values() {
+    use frame_support::traits::ConstU128;
+    use frame_support::weights::ConstantMultiplier;
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+    type OverflowTrader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConstantMultiplier<u128, ConstU128<{ Balance::MAX }>>,
+        MockOracle,
+        MockConvert,
+        (),
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let weight = WeightV2::from_ref_time(1_000_000);
+
+    assert_eq!(Trader::compute_fee(weight, &core_id, Price::one()), Ok(1_000_000));
+    assert_eq!(
+        Trader::compute_fee(weight, &core_id, Price::from_rational(1, 2)),
+        Ok(500_000)
+    );
+    assert_eq!(
+        Trader::compute_fee(weight, &core_id, Price::from_rational(1, 3)),
+        Ok(333_333)
+    );
+    assert_eq!(Trader::compute_fee(WeightV2::zero(), &core_id, Price::one()), Ok(0));
+    assert_eq!(
+        OverflowTrader::compute_fee(
+            WeightV2::from_ref_time(1_000),
+            &core_id,
+            Price::saturating_from_integer(2)
+        ),
+        Err(XcmError::Overflow)
+    );
+}
+
 #[test]
 fn refunds_first_asset_completely() {
     ExpectRevenue::reset();
@@ -388,12 +318,4548 @@ fn revenue_goes_to_fee_receiver() {
 #[macro_export]
 macro_rules! assert_that_fee_is_deposited {
     () => {
-        EXPECTED_DEPOSITS.with(|remaining| {
-            assert!(
-                remaining.borrow().is_empty(),
-                "There should be no expected fees remaining. Remaining: {:?}",
-                remaining
-            );
-        });
+        ExpectDeposit::expect_no_remaining_fees();
     };
 }
+
+/// Mock charge increment which rounds amounts of `TEST_ASSET_ID` to multiples of 1_000.
+struct MockIncrement;
+impl Convert<AssetId, Option<u128>> for MockIncrement {
+    fn convert(id: AssetId) -> Option<u128> {
+        match id {
+            TEST_ASSET_ID => Some(1_000),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn charge_is_rounded_up_to_increment() {
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // 0.5 * 1_001_000 = 500_500 which is rounded up to 501_000
+        let test_payment: MultiAsset = (Concrete(test_id.clone()), 600_000).into();
+        let res = dbg!(trader.buy_weight(1_001_000, test_payment.into()));
+        let expected_unused: MultiAsset = (Concrete(test_id.clone()), 99_000).into();
+        assert_eq!(res, Ok(expected_unused.into()));
+        ExpectRevenue::register_expected_asset((Concrete(test_id), 501_000).into());
+
+        // assets without increment are not rounded
+        let core_payment: MultiAsset = (Concrete(core_id), 1_001).into();
+        let res = dbg!(trader.buy_weight(1_001, core_payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == weight")
+            .is_empty());
+        ExpectRevenue::register_expected_asset(core_payment);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn refund_is_rounded_down_to_increment() {
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    let test_payment: MultiAsset = (Concrete(test_id.clone()), 501_000).into();
+    let res = dbg!(trader.buy_weight(1_001_000, test_payment.into()));
+    assert!(res.expect("buy_weight should succeed").is_empty());
+
+    // 0.5 * 3_000 = 1_500 which is rounded down to 1_000
+    assert_eq!(
+        trader.refund_weight(3_000),
+        Some((Concrete(test_id.clone()), 1_000).into())
+    );
+    // 0.5 * 1_000 = 500 which is rounded down to 0, so nothing is refunded and the weight is kept
+    let bought_weight = trader.bought_weight();
+    assert_eq!(trader.refund_weight(1_000), None);
+    assert_eq!(trader.bought_weight(), bought_weight);
+    assert_eq!((trader.refunds(), trader.paid_assets().count()), (1, 1));
+}
+
+#[test]
+fn price_source_uses_trader_prices() {
+    use crate::oracles::{AssetPriceSource, OraclePriceSource};
+
+    ExpectRevenue::reset();
+    type PriceSource = OraclePriceSource<AssetId, Price, MockConvert, MockOracle>;
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let test_asset: MultiAsset = (Concrete(test_id.clone()), 1).into();
+    let cheap_asset: MultiAsset = (Concrete(cheap_id), 1).into();
+    let core_asset: MultiAsset = (Concrete(core_id), 1).into();
+    let unknown_asset: MultiAsset = (Concrete(GeneralKey(9876u32.encode().try_into().unwrap()).into()), 1).into();
+
+    let price = PriceSource::price(&test_asset).expect("test asset should be priced");
+    assert_eq!(price, Price::from_float(0.5));
+    assert_eq!(PriceSource::price(&unknown_asset), None);
+    assert_eq!(
+        PriceSource::exchange_rate(&core_asset, &cheap_asset),
+        Some(Price::saturating_from_integer(4))
+    );
+    assert_eq!(
+        PriceSource::exchange_rate(&test_asset, &cheap_asset),
+        Some(Price::saturating_from_integer(8))
+    );
+    assert_eq!(PriceSource::exchange_rate(&test_asset, &unknown_asset), None);
+
+    {
+        let mut trader = Trader::new();
+
+        let weight = 1_000_000;
+        let expected_fee = price.saturating_mul_int(weight as u128);
+        let payment: MultiAsset = (Concrete(test_id.clone()), expected_fee).into();
+        let res = dbg!(trader.buy_weight(weight, payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == price * weight")
+            .is_empty());
+        ExpectRevenue::register_expected_asset(payment);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn skips_zero_amount_asset_when_selecting() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        let mut payment = Assets::new();
+        payment.subsume((Concrete(core_id), 0).into());
+        let test_payment: MultiAsset = (Concrete(test_id), 500_000).into();
+        payment.subsume(test_payment.clone());
+
+        let res = dbg!(trader.buy_weight(1_000_000, payment));
+        assert!(res
+            .expect("buy_weight should succeed with the second asset because payment == 0.5 * weight")
+            .fungible_assets_iter()
+            .all(|asset| asset.fun == Fungible(0)));
+        ExpectRevenue::register_expected_asset(test_payment);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn refund_is_capped_per_call() {
+    use frame_support::traits::ConstU64;
+
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type MaxRefundWeightPerCall = ConstU64<250_000>;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        let weight = 1_000_000;
+        let core_payment: MultiAsset = (Concrete(core_id.clone()), 1_000_000).into();
+        let res = dbg!(trader.buy_weight(weight, core_payment.into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == weight")
+            .is_empty());
+
+        let partial_refund: MultiAsset = (Concrete(core_id), 250_000).into();
+        for _ in 0..4 {
+            assert_eq!(trader.refund_weight(weight), Some(partial_refund.clone()));
+        }
+        assert_eq!(trader.refund_weight(weight), None);
+    }
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn sanity_checked_oracle_rejects_diverging_prices() {
+    use crate::oracles::{MaxDivergence, SanityCheckedOracle};
+    use frame_support::parameter_types;
+    use sp_runtime::Permill;
+
+    parameter_types! {
+        pub const FivePercent: Permill = Permill::from_percent(5);
+    }
+
+    /// Reference oracle which agrees with `MockOracle` within 5% except for `CHEAP_ASSET_ID`.
+    struct ReferenceOracle;
+    impl NativePriceOracle<AssetId, Price> for ReferenceOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                CORE_ASSET_ID => Some(Price::one()),
+                TEST_ASSET_ID => Some(Price::from_float(0.51)),
+                CHEAP_ASSET_ID => Some(Price::saturating_from_integer(5)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = SanityCheckedOracle<MockOracle, ReferenceOracle, MaxDivergence<FivePercent>>;
+
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::from_float(0.5)));
+    assert_eq!(Oracle::price(CHEAP_ASSET_ID), None);
+    // the reference oracle has no price so it cannot be verified
+    assert_eq!(Oracle::price(OVERFLOW_ASSET_ID), None);
+}
+
+thread_local! {
+    pub static OTHER_REVENUE: RefCell<Vec<MultiAsset>> = RefCell::new(Vec::new());
+}
+
+/// Revenue sink recording taken assets separately from `ExpectRevenue`.
+struct OtherRevenue;
+impl TakeRevenue for OtherRevenue {
+    fn take_revenue(asset: MultiAsset) {
+        OTHER_REVENUE.with(|r| r.borrow_mut().push(asset));
+    }
+}
+
+#[test]
+fn can_settle_to_sink_chosen_at_call_time() {
+    ExpectRevenue::reset();
+    OTHER_REVENUE.with(|r| r.borrow_mut().clear());
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let core_payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    let test_payment: MultiAsset = (Concrete(test_id), 500_000).into();
+
+    {
+        let mut trader = Trader::new();
+        let res = dbg!(trader.buy_weight(1_000_000, core_payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == weight")
+            .is_empty());
+        trader.settle_to::<OtherRevenue>();
+    }
+    // nothing is left to be taken on drop
+    ExpectRevenue::expect_no_revenue();
+
+    {
+        let mut trader = Trader::new();
+        let res = dbg!(trader.buy_weight(1_000_000, test_payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == 0.5 * weight")
+            .is_empty());
+        trader.settle_to::<ExpectRevenue>();
+        ExpectRevenue::register_expected_asset(test_payment.clone());
+        ExpectRevenue::expect_revenue();
+    }
+
+    TAKEN_REVENUE.with(|t| assert_eq!(*t.borrow(), BTreeSet::from([test_payment])));
+    OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![core_payment]));
+}
+
+#[test]
+fn dust_is_swept_into_final_refund_if_enabled() {
+    use frame_support::traits::ConstBool;
+
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+            type SweepDustToRefund = ConstBool<true>;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // 0.5 * 1_001_000 = 500_500 which is rounded up to 501_000
+        let test_payment: MultiAsset = (Concrete(test_id.clone()), 501_000).into();
+        let res = dbg!(trader.buy_weight(1_001_000, test_payment.into()));
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        assert_eq!(
+            trader.refund_weight(1_000_000),
+            Some((Concrete(test_id.clone()), 500_000).into())
+        );
+        // 0.5 * 1_000 is rounded down to 0, but the remaining dust is swept into the final refund
+        assert_eq!(trader.refund_weight(1_000), Some((Concrete(test_id), 1_000).into()));
+    }
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn dust_is_taken_as_revenue_by_default() {
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        let test_payment: MultiAsset = (Concrete(test_id.clone()), 501_000).into();
+        let res = dbg!(trader.buy_weight(1_001_000, test_payment.into()));
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        assert_eq!(
+            trader.refund_weight(1_000_000),
+            Some((Concrete(test_id.clone()), 500_000).into())
+        );
+        assert_eq!(trader.refund_weight(1_000), None);
+        ExpectRevenue::register_expected_asset((Concrete(test_id), 1_000).into());
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn triangulated_oracle_uses_intermediate_price() {
+    use crate::oracles::TriangulatedOracle;
+    use frame_support::traits::ConstU32;
+
+    const TRIANGULATED_ASSET_ID: AssetId = 777;
+
+    /// Prices assets in terms of `TEST_ASSET_ID`.
+    struct ViaTestAsset;
+    impl NativePriceOracle<AssetId, Price> for ViaTestAsset {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                TRIANGULATED_ASSET_ID => Some(Price::saturating_from_integer(3)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = TriangulatedOracle<MockOracle, ViaTestAsset, ConstU32<TEST_ASSET_ID>>;
+    type UnpricedIntermediate = TriangulatedOracle<MockOracle, ViaTestAsset, ConstU32<9876>>;
+
+    // direct prices take precedence
+    assert_eq!(Oracle::price(CHEAP_ASSET_ID), Some(Price::saturating_from_integer(4)));
+    // 3 * 0.5 = 1.5
+    assert_eq!(Oracle::price(TRIANGULATED_ASSET_ID), Some(Price::from_float(1.5)));
+    assert_eq!(Oracle::price(9876), None);
+    assert_eq!(UnpricedIntermediate::price(TRIANGULATED_ASSET_ID), None);
+}
+
+#[test]
+fn revenue_is_emitted_once_on_trap() {
+    OTHER_REVENUE.with(|r| r.borrow_mut().clear());
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, OtherRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let core_payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+
+    {
+        let mut trader = Trader::new();
+        let res = dbg!(trader.buy_weight(1_000_000, core_payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == weight")
+            .is_empty());
+
+        trader.emit_on_trap();
+        OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![core_payment.clone()]));
+        assert_eq!(trader.refund_weight(1_000_000), None);
+    }
+    OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![core_payment]));
+}
+
+#[test]
+fn compare_fees_reports_deltas_between_configs() {
+    use crate::inspect::{compare_fees, FeeDelta};
+    use frame_support::traits::ConstU128;
+    use frame_support::weights::ConstantMultiplier;
+
+    type Old = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+    type New = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConstantMultiplier<Balance, ConstU128<2>>,
+        MockOracle,
+        MockConvert,
+        (),
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let unknown_id: MultiLocation = GeneralKey(9876u32.encode().try_into().unwrap()).into();
+
+    let deltas = compare_fees::<Old, New>(&[1_000, 2_000], &[core_id.clone(), test_id.clone(), unknown_id.clone()]);
+
+    assert_eq!(
+        deltas,
+        vec![
+            FeeDelta {
+                weight: 1_000,
+                location: core_id.clone(),
+                old_fee: Some(1_000),
+                new_fee: Some(2_000),
+            },
+            FeeDelta {
+                weight: 1_000,
+                location: test_id.clone(),
+                old_fee: Some(500),
+                new_fee: Some(1_000),
+            },
+            FeeDelta {
+                weight: 1_000,
+                location: unknown_id.clone(),
+                old_fee: None,
+                new_fee: None,
+            },
+            FeeDelta {
+                weight: 2_000,
+                location: core_id,
+                old_fee: Some(2_000),
+                new_fee: Some(4_000),
+            },
+            FeeDelta {
+                weight: 2_000,
+                location: test_id,
+                old_fee: Some(1_000),
+                new_fee: Some(2_000),
+            },
+            FeeDelta {
+                weight: 2_000,
+                location: unknown_id,
+                old_fee: None,
+                new_fee: None,
+            },
+        ]
+    );
+    assert_eq!(
+        deltas.iter().map(FeeDelta::difference).collect::<Vec<_>>(),
+        vec![Some(1_000), Some(500), None, Some(2_000), Some(1_000), None]
+    );
+}
+
+#[test]
+fn native_share_of_fee_is_charged_in_native_asset() {
+    use frame_support::parameter_types;
+    use sp_runtime::Permill;
+
+    parameter_types! {
+        pub NativeLocation: Option<MultiLocation> = MockConvert::convert(CORE_ASSET_ID);
+        pub const NativeShare: Permill = Permill::from_percent(20);
+    }
+
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type NativeAssetLocation = NativeLocation;
+            type NativeFeeShare = NativeShare;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // 20% of the weight is paid in the native asset, the remaining 80% at a price of 0.5
+        let test_payment: MultiAsset = (Concrete(test_id.clone()), 400_000).into();
+        let mut payment = Assets::new();
+        payment.subsume((Concrete(core_id.clone()), 200_000).into());
+        payment.subsume((Concrete(test_id.clone()), 500_000).into());
+
+        let res = dbg!(trader.buy_weight(1_000_000, payment));
+        let expected_unused: MultiAsset = (Concrete(test_id.clone()), 100_000).into();
+        assert_eq!(res, Ok(expected_unused.into()));
+        ExpectRevenue::register_expected_asset(test_payment);
+
+        // the native share is required
+        let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id), 500_000).into()));
+        assert_eq!(res, Err(XcmError::TooExpensive));
+
+        // paying with the native asset only charges the whole fee in the native asset
+        let res = dbg!(trader.buy_weight(1_000, (Concrete(core_id.clone()), 1_000).into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == weight")
+            .is_empty());
+        ExpectRevenue::register_expected_asset((Concrete(core_id), 201_000).into());
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn revenue_for_disallowed_receiver_goes_to_fallback() {
+    use frame_support::parameter_types;
+
+    ExpectDeposit::reset();
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    struct AllowedReceivers;
+    impl Contains<AccountId> for AllowedReceivers {
+        fn contains(who: &AccountId) -> bool {
+            *who == 7
+        }
+    }
+
+    parameter_types! {
+        pub const Fallback: Option<AccountId> = Some(7);
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        AllowedReceivers,
+        Fallback,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    ExpectDeposit::register_expected_fee(7, CORE_ASSET_ID, 1234);
+
+    Revenue::take_revenue((core_id, 1234).into());
+
+    assert_that_fee_is_deposited!();
+}
+
+#[test]
+fn revenue_is_routed_to_receiver_of_asset() {
+    use frame_support::traits::Everything;
+
+    ExpectDeposit::reset();
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    struct ReceiverOf;
+    impl Convert<AssetId, Option<AccountId>> for ReceiverOf {
+        fn convert(id: AssetId) -> Option<AccountId> {
+            match id {
+                TEST_ASSET_ID => Some(1),
+                CHEAP_ASSET_ID => Some(2),
+                _ => None,
+            }
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        (),
+        ReceiverOf,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    ExpectDeposit::register_expected_fee(1, TEST_ASSET_ID, 100);
+    ExpectDeposit::register_expected_fee(2, CHEAP_ASSET_ID, 200);
+    // unmapped asset goes to the default fee receiver
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 300);
+
+    Revenue::take_revenue((test_id, 100).into());
+    Revenue::take_revenue((cheap_id, 200).into());
+    Revenue::take_revenue((core_id, 300).into());
+
+    assert_that_fee_is_deposited!();
+}
+
+#[test]
+fn buys_with_cheapest_asset() {
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+            type Selector = CheapestAssetSelector;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // The core asset cannot cover the fee and the fee in the test asset is rounded up to
+        // 501_000 (worth 1_002_000 native), so the cheap asset (4_002_000 worth 1_000_500 native)
+        // is charged.
+        let mut payment = Assets::new();
+        payment.subsume((Concrete(core_id.clone()), 10).into());
+        payment.subsume((Concrete(test_id.clone()), 600_000).into());
+        payment.subsume((Concrete(cheap_id.clone()), 5_000_000).into());
+
+        let res = dbg!(trader.buy_weight(1_000_500, payment));
+
+        let mut expected_unused = Assets::new();
+        expected_unused.subsume((Concrete(core_id), 10).into());
+        expected_unused.subsume((Concrete(test_id), 600_000).into());
+        expected_unused.subsume((Concrete(cheap_id.clone()), 998_000).into());
+        assert_eq!(res, Ok(expected_unused));
+        ExpectRevenue::register_expected_asset((Concrete(cheap_id), 4_002_000).into());
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn cheapest_asset_ties_are_broken_by_location() {
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type Selector = CheapestAssetSelector;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+    assert!(core_id < cheap_id);
+
+    {
+        let mut trader = Trader::new();
+
+        let core_payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+        let cheap_payment: MultiAsset = (Concrete(cheap_id), 4_000_000).into();
+        let mut payment = Assets::new();
+        payment.subsume(cheap_payment.clone());
+        payment.subsume(core_payment.clone());
+
+        let res = dbg!(trader.buy_weight(1_000_000, payment));
+        assert_eq!(res, Ok(cheap_payment.into()));
+        ExpectRevenue::register_expected_asset(core_payment);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn marginal_rate_matches_buy_weight_pricing() {
+    use frame_support::parameter_types;
+    use frame_support::traits::ConstU128;
+    use frame_support::weights::ConstantMultiplier;
+
+    parameter_types! {
+        pub TenPercent: Price = Price::saturating_from_rational(1, 10);
+    }
+
+    struct MockDeniedAssets;
+    impl Contains<AssetId> for MockDeniedAssets {
+        fn contains(id: &AssetId) -> bool {
+            *id == TEST_ASSET_ID
+        }
+    }
+
+    type FeeCurve = ConstantMultiplier<Balance, ConstU128<3>>;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type DeniedAssets = MockDeniedAssets;
+            type Surcharge = TenPercent;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, FeeCurve, MockOracle, MockConvert, (), Config>;
+
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let unknown_id: MultiLocation = GeneralKey(9876u32.encode().try_into().unwrap()).into();
+
+    // 4 * 3 plus the surcharge of 10%, truncated
+    let rate = Trader::marginal_rate(&cheap_id);
+    assert_eq!(rate, Some((Price::saturating_from_integer(4), 13)));
+    // denied and unknown assets have no rate
+    assert_eq!(Trader::marginal_rate(&test_id), None);
+    assert_eq!(Trader::marginal_rate(&unknown_id), None);
+
+    let (_, amount) = rate.unwrap();
+    let mut trader = Trader::new();
+    let payment: MultiAsset = (Concrete(cheap_id), amount).into();
+    let res = trader.buy_weight(1, payment.into());
+    assert!(res
+        .expect("buy_weight should succeed because payment == marginal rate")
+        .is_empty());
+}
+
+struct ConfigWithSelector<Selector>(PhantomData<Selector>);
+trader_config! {
+    impl<Selector: AssetSelector<Price>> TraderConfig<AssetId, Balance, Price> for ConfigWithSelector<Selector> {
+        type Selector = Selector;
+    }
+}
+
+/// Trader using the given `AssetSelector` with otherwise default configuration.
+type TraderWithSelector<Selector> = MultiCurrencyTrader<
+    AssetId,
+    Balance,
+    Price,
+    IdentityFee<Balance>,
+    MockOracle,
+    MockConvert,
+    (),
+    ConfigWithSelector<Selector>,
+>;
+
+#[test]
+fn first_asset_selector_charges_first_asset() {
+    use crate::selector::FirstAssetSelector;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(test_id.clone()), 10).into());
+    payment.subsume((Concrete(cheap_id.clone()), 4_000_000).into());
+
+    let mut trader = TraderWithSelector::<FirstAssetSelector>::new();
+    let res = dbg!(trader.buy_weight(1_000_000, payment.clone()));
+    assert_eq!(res, Err(XcmError::TooExpensive));
+
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(test_id.clone()), 500_000).into());
+    payment.subsume((Concrete(cheap_id.clone()), 4_000_000).into());
+
+    let res = dbg!(trader.buy_weight(1_000_000, payment));
+    assert_eq!(res, Ok(MultiAsset::from((Concrete(cheap_id), 4_000_000)).into()));
+}
+
+#[test]
+fn custom_asset_selector_is_used() {
+    /// Selects the last asset in the payment.
+    struct LastAssetSelector;
+    impl AssetSelector<Price> for LastAssetSelector {
+        fn select<'a>(
+            candidates: impl Iterator<Item = (&'a MultiLocation, u128)>,
+            _price_of: impl Fn(&'a MultiLocation) -> Option<Price>,
+            _charge_of: impl Fn(&'a MultiLocation, Price) -> Option<u128>,
+        ) -> Option<&'a MultiLocation> {
+            candidates.last().map(|(location, _)| location)
+        }
+    }
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let core_payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    let mut payment = Assets::new();
+    payment.subsume(core_payment.clone());
+    payment.subsume((Concrete(cheap_id), 4_000_000).into());
+
+    let mut trader = TraderWithSelector::<LastAssetSelector>::new();
+    let res = dbg!(trader.buy_weight(1_000_000, payment));
+    assert_eq!(res, Ok(core_payment.into()));
+}
+
+#[test]
+fn selection_does_not_depend_on_payment_order() {
+    use crate::selector::FirstAssetSelector;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let assets: [MultiAsset; 3] = [
+        (Concrete(cheap_id), 4_000_000).into(),
+        (Concrete(core_id), 10).into(),
+        (Concrete(test_id), 600_000).into(),
+    ];
+    let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+
+    let outcomes = |buy: &dyn Fn(Assets) -> Result<Assets, XcmError>| {
+        orders
+            .iter()
+            .map(|order| {
+                let payment: Vec<MultiAsset> = order.iter().map(|i| assets[*i].clone()).collect();
+                buy(payment.into())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for weight in [10, 1_000_000, 1_200_000] {
+        let cheapest =
+            outcomes(&|payment| TraderWithSelector::<CheapestAssetSelector>::new().buy_weight(weight, payment));
+        assert!(cheapest.iter().all(|outcome| *outcome == cheapest[0]), "{:?}", cheapest);
+
+        let first = outcomes(&|payment| TraderWithSelector::<FirstAssetSelector>::new().buy_weight(weight, payment));
+        assert!(first.iter().all(|outcome| *outcome == first[0]), "{:?}", first);
+    }
+}
+
+#[test]
+fn falls_back_to_next_asset_without_price() {
+    use crate::selector::FirstAssetSelector;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let unpriced_id: MultiLocation = GeneralKey(1u32.encode().try_into().unwrap()).into();
+    let other_unpriced_id: MultiLocation = GeneralKey(9876u32.encode().try_into().unwrap()).into();
+    assert!(unpriced_id < test_id);
+
+    let unpriced_payment: MultiAsset = (Concrete(unpriced_id), 1_000_000).into();
+
+    let mut payment = Assets::new();
+    payment.subsume(unpriced_payment.clone());
+    payment.subsume((Concrete(test_id), 500_000).into());
+
+    let res = dbg!(TraderWithSelector::<FirstAssetSelector>::new().buy_weight(1_000_000, payment.clone()));
+    assert_eq!(res, Ok(unpriced_payment.clone().into()));
+    let res = dbg!(TraderWithSelector::<CheapestAssetSelector>::new().buy_weight(1_000_000, payment));
+    assert_eq!(res, Ok(unpriced_payment.clone().into()));
+
+    let mut payment = Assets::new();
+    payment.subsume(unpriced_payment);
+    payment.subsume((Concrete(other_unpriced_id), 1_000_000).into());
+
+    let res = dbg!(TraderWithSelector::<FirstAssetSelector>::new().buy_weight(1_000_000, payment.clone()));
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+    let res = dbg!(TraderWithSelector::<CheapestAssetSelector>::new().buy_weight(1_000_000, payment));
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+}
+
+/// Derives sovereign accounts of sibling parachains as `1_000 + para_id`.
+struct MockSovereignAccountOf;
+impl xcm_executor::traits::Convert<MultiLocation, AccountId> for MockSovereignAccountOf {
+    fn convert(location: MultiLocation) -> Result<AccountId, MultiLocation> {
+        match location {
+            MultiLocation {
+                parents: 1,
+                interior: X1(Parachain(id)),
+            } => Ok(1_000 + id),
+            _ => Err(location),
+        }
+    }
+}
+
+#[test]
+fn reciprocal_share_goes_to_sovereign_account_of_origin() {
+    use crate::revenue::ReciprocalRebate;
+    use frame_support::parameter_types;
+    use sp_runtime::Permill;
+
+    ExpectRevenue::reset();
+    ExpectDeposit::reset();
+
+    parameter_types! {
+        pub const ReciprocalShare: Permill = Permill::from_percent(10);
+    }
+
+    type Rebate = ReciprocalRebate<
+        AccountId,
+        AssetId,
+        Balance,
+        MockConvert,
+        ExpectDeposit,
+        MockSovereignAccountOf,
+        ReciprocalShare,
+        ExpectRevenue,
+    >;
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let origin = MultiLocation::new(1, X1(Parachain(2_000)));
+
+    let mut trader = Trader::new();
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+
+    ExpectDeposit::register_expected_fee(3_000, CORE_ASSET_ID, 100_000);
+    ExpectRevenue::register_expected_asset((Concrete(core_id.clone()), 900_000).into());
+
+    trader.settle_with_origin::<Rebate>(&origin);
+
+    assert_that_fee_is_deposited!();
+    ExpectRevenue::expect_revenue();
+
+    // without a sovereign account everything goes to the main receiver
+    ExpectRevenue::reset();
+    Rebate::take_revenue_from(&MultiLocation::parent(), (core_id.clone(), 1_000).into());
+    ExpectRevenue::register_expected_asset((core_id, 1_000).into());
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn settlement_preview_matches_refund_and_revenue() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        let payment: MultiAsset = (Concrete(test_id.clone()), 500_000).into();
+        let res = dbg!(trader.buy_weight(1_000_000, payment.into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == 0.5 * weight")
+            .is_empty());
+
+        let expected_refund: MultiAsset = (Concrete(test_id.clone()), 200_000).into();
+        let expected_revenue: MultiAsset = (Concrete(test_id), 300_000).into();
+        let (refund, revenue) = trader.preview_settlement(400_000);
+        assert_eq!(refund, Some(expected_refund.clone()));
+        assert_eq!(revenue, vec![expected_revenue.clone()]);
+
+        // previewing does not change the state of the trader
+        assert_eq!(trader.preview_settlement(400_000), (refund.clone(), revenue));
+
+        assert_eq!(trader.refund_weight(400_000), refund);
+        ExpectRevenue::register_expected_asset(expected_revenue);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn native_asset_is_preferred_if_enabled() {
+    use frame_support::parameter_types;
+    use frame_support::traits::ConstBool;
+
+    parameter_types! {
+        pub NativeLocation: Option<MultiLocation> = MockConvert::convert(CORE_ASSET_ID);
+    }
+
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type NativeAssetLocation = NativeLocation;
+            type PreferNativeAsset = ConstBool<true>;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // the test asset is cheaper, but the native asset is charged because it is present
+        let mut payment = Assets::new();
+        payment.subsume((Concrete(test_id.clone()), 500_000).into());
+        payment.subsume((Concrete(core_id.clone()), 2_000_000).into());
+
+        let res = dbg!(trader.buy_weight(1_000_000, payment));
+        let mut expected_unused = Assets::new();
+        expected_unused.subsume((Concrete(test_id.clone()), 500_000).into());
+        expected_unused.subsume((Concrete(core_id.clone()), 1_000_000).into());
+        assert_eq!(res, Ok(expected_unused));
+        ExpectRevenue::register_expected_asset((Concrete(core_id), 1_000_000).into());
+
+        // without the native asset the selector decides
+        let test_payment: MultiAsset = (Concrete(test_id), 500_000).into();
+        let res = dbg!(trader.buy_weight(1_000_000, test_payment.clone().into()));
+        assert!(res
+            .expect("buy_weight should succeed because payment == 0.5 * weight")
+            .is_empty());
+        ExpectRevenue::register_expected_asset(test_payment);
+    }
+    ExpectRevenue::expect_revenue();
+}
+
+#[test]
+fn only_accepted_assets_can_pay_for_weight() {
+    struct MockAcceptedAssets;
+    impl Contains<AssetId> for MockAcceptedAssets {
+        fn contains(id: &AssetId) -> bool {
+            matches!(*id, CORE_ASSET_ID | TEST_ASSET_ID)
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type AcceptedAssets = MockAcceptedAssets;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // the cheap asset has a price but is not accepted
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(cheap_id.clone()), 4_000_000).into()));
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+
+    // the accepted asset is charged even if a non-accepted one is presented as well
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(cheap_id.clone()), 4_000_000).into());
+    payment.subsume((Concrete(test_id), 500_000).into());
+    let res = dbg!(trader.buy_weight(1_000_000, payment));
+    let expected_unused: MultiAsset = (Concrete(cheap_id), 4_000_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+}
+
+#[test]
+fn low_confidence_prices_are_rejected() {
+    use crate::oracles::{ConfidenceCheckedOracle, ConfidentPriceOracle};
+    use frame_support::traits::ConstU128;
+
+    /// Oracle reporting the prices of `MockOracle` with low confidence in `TEST_ASSET_ID`.
+    struct MockConfidentOracle;
+    impl ConfidentPriceOracle<AssetId, Price> for MockConfidentOracle {
+        type Confidence = u128;
+
+        fn price_with_confidence(currency: AssetId) -> Option<(Price, u128)> {
+            let confidence = match currency {
+                TEST_ASSET_ID => 999,
+                _ => 1_000_000,
+            };
+            MockOracle::price(currency).map(|price| (price, confidence))
+        }
+    }
+
+    type Oracle = ConfidenceCheckedOracle<MockConfidentOracle, ConstU128<1_000>>;
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    assert_eq!(Oracle::price(TEST_ASSET_ID), None);
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, Oracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id), 500_000).into()));
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id), 1_000_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+}
+
+#[test]
+fn denied_assets_cannot_pay_for_weight() {
+    struct MockDeniedAssets;
+    impl Contains<AssetId> for MockDeniedAssets {
+        fn contains(id: &AssetId) -> bool {
+            *id == TEST_ASSET_ID
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type DeniedAssets = MockDeniedAssets;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // the test asset is priced but denied
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id), 500_000).into()));
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id), 1_000_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+}
+
+#[test]
+fn refund_can_be_resolved_to_asset_id_and_balance() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id), 500_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == 0.5 * weight")
+        .is_empty());
+
+    let refund = trader.refund_weight(400_000).expect("should refund");
+    assert_eq!(Trader::asset_id_and_balance(&refund), Some((TEST_ASSET_ID, 200_000)));
+
+    let unknown: MultiAsset = (Concrete(GeneralKey(9876u32.encode().try_into().unwrap()).into()), 1_000).into();
+    assert_eq!(Trader::asset_id_and_balance(&unknown), None);
+}
+
+#[test]
+fn buys_and_refunds_are_counted() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert_eq!((trader.buys(), trader.refunds()), (0, 0));
+
+    assert!(trader
+        .buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into())
+        .is_ok());
+    assert!(trader
+        .buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into())
+        .is_ok());
+    // failed buys are not counted
+    assert!(trader.buy_weight(1_000_000, (Concrete(core_id), 1).into()).is_err());
+
+    assert!(trader.refund_weight(500_000).is_some());
+    assert!(trader.refund_weight(1_500_000).is_some());
+    // nothing left to refund
+    assert!(trader.refund_weight(1_000).is_none());
+
+    assert_eq!((trader.buys(), trader.refunds()), (2, 2));
+}
+
+#[test]
+fn stale_prices_are_rejected() {
+    use crate::oracles::NativePriceOracleWithTime;
+    use frame_support::traits::ConstU64;
+
+    /// Oracle reporting the prices of `MockOracle` with hard-coded update times. The native asset
+    /// has no price, as it is priced by `MockOracle` instead.
+    struct MockTimedOracle;
+    impl NativePriceOracleWithTime<AssetId, Price, u64> for MockTimedOracle {
+        fn price_with_time(currency: AssetId) -> Option<(Price, u64)> {
+            let updated_at = match currency {
+                CORE_ASSET_ID => return None,
+                TEST_ASSET_ID => 950,
+                CHEAP_ASSET_ID => 900,
+                _ => 899,
+            };
+            MockOracle::price(currency).map(|price| (price, updated_at))
+        }
+    }
+
+    parameter_types! {
+        pub NativeLocation: Option<MultiLocation> = MockConvert::convert(CORE_ASSET_ID);
+        pub MaxPriceAge: Option<u64> = Some(100);
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type NativeAssetLocation = NativeLocation;
+            type TimedPrices = MockTimedOracle;
+            type MaxPriceAge = MaxPriceAge;
+            type Now = ConstU64<1_000>;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+    let overflow_id = MockConvert::convert(OVERFLOW_ASSET_ID).unwrap();
+
+    // the native asset is exempt
+    let mut trader = Trader::new();
+    assert!(trader.buy_weight(1_000, (Concrete(core_id), 1_000).into()).is_ok());
+    // fresh
+    let mut trader = Trader::new();
+    assert!(trader
+        .buy_weight(1_000, (Concrete(test_id.clone()), 500).into())
+        .is_ok());
+    // exactly at the limit
+    let mut trader = Trader::new();
+    assert!(trader.buy_weight(1_000, (Concrete(cheap_id), 4_000).into()).is_ok());
+    // stale
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000, (Concrete(overflow_id.clone()), u128::MAX).into());
+    assert_eq!(res, Err(STALE_PRICE));
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+
+    // fresh assets in the payment still pay
+    let mut trader = Trader::new();
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(overflow_id), u128::MAX).into());
+    payment.subsume((Concrete(test_id.clone()), 500).into());
+    assert!(trader.buy_weight(1_000, payment).is_ok());
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&test_id, &Price::from_float(0.5), &500)]
+    );
+}
+
+thread_local! {
+    pub static INFLATION_TO_OFFSET: RefCell<u128> = RefCell::new(0);
+    pub static BURNT_REVENUE: RefCell<Vec<MultiAsset>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn revenue_is_burnt_up_to_inflation_and_excess_goes_to_treasury() {
+    use crate::revenue::{BalancingBurnRevenue, InflationToOffset};
+
+    /// Inflation of the core asset still to be offset, other assets are never burnt.
+    struct MockInflation;
+    impl InflationToOffset for MockInflation {
+        fn inflation_to_offset(location: &MultiLocation) -> u128 {
+            if Some(location.clone()) == MockConvert::convert(CORE_ASSET_ID) {
+                INFLATION_TO_OFFSET.with(|i| *i.borrow())
+            } else {
+                0
+            }
+        }
+
+        fn note_burned(_location: &MultiLocation, amount: u128) {
+            INFLATION_TO_OFFSET.with(|i| *i.borrow_mut() -= amount);
+        }
+    }
+
+    struct MockBurner;
+    impl TakeRevenue for MockBurner {
+        fn take_revenue(asset: MultiAsset) {
+            BURNT_REVENUE.with(|r| r.borrow_mut().push(asset));
+        }
+    }
+
+    type Revenue = BalancingBurnRevenue<MockBurner, MockInflation, OtherRevenue>;
+
+    OTHER_REVENUE.with(|r| r.borrow_mut().clear());
+    INFLATION_TO_OFFSET.with(|i| *i.borrow_mut() = 1_000);
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // fees under the inflation target are burnt completely
+    Revenue::take_revenue((Concrete(core_id.clone()), 600).into());
+    // only the remaining inflation is burnt, the excess goes to the treasury
+    Revenue::take_revenue((Concrete(core_id.clone()), 1_000).into());
+    // there is no inflation to offset in other assets
+    Revenue::take_revenue((Concrete(test_id.clone()), 500).into());
+
+    let burnt_core: MultiAsset = (Concrete(core_id.clone()), 600).into();
+    let burnt_rest: MultiAsset = (Concrete(core_id.clone()), 400).into();
+    BURNT_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![burnt_core, burnt_rest]));
+    let excess_core: MultiAsset = (Concrete(core_id), 600).into();
+    let excess_test: MultiAsset = (Concrete(test_id), 500).into();
+    OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![excess_core, excess_test]));
+    assert_eq!(INFLATION_TO_OFFSET.with(|i| *i.borrow()), 0);
+}
+
+thread_local! {
+    pub static PRICE_QUERIES: RefCell<Vec<AssetId>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn each_asset_is_priced_once_per_buy() {
+    /// Oracle reporting the prices of `MockOracle` and recording every query.
+    struct CountingOracle;
+    impl NativePriceOracle<AssetId, Price> for CountingOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            PRICE_QUERIES.with(|q| q.borrow_mut().push(currency));
+            MockOracle::price(currency)
+        }
+    }
+
+    // the cheapest asset selector prices all assets
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type Selector = CheapestAssetSelector;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, CountingOracle, MockConvert, (), Config>;
+
+    PRICE_QUERIES.with(|q| q.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(test_id.clone()), 250_000).into());
+    payment.subsume((Concrete(core_id), 2_000_000).into());
+    payment.subsume((Concrete(test_id), 250_000).into());
+
+    let mut trader = Trader::new();
+    assert!(trader.buy_weight(1_000_000, payment).is_ok());
+
+    PRICE_QUERIES.with(|q| {
+        let mut queries = q.borrow().clone();
+        queries.sort();
+        assert_eq!(queries, vec![CORE_ASSET_ID, TEST_ASSET_ID]);
+    });
+}
+
+#[test]
+fn median_oracle_ignores_outlier() {
+    use crate::oracles::MedianOracle;
+
+    /// Oracle reporting the price of `TEST_ASSET_ID` only, scaled by a factor.
+    struct ScaledOracle<const PERCENT: u128>;
+    impl<const PERCENT: u128> NativePriceOracle<AssetId, Price> for ScaledOracle<PERCENT> {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                TEST_ASSET_ID => Some(Price::saturating_from_rational(PERCENT, 100)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = MedianOracle<(ScaledOracle<50>, ScaledOracle<5_000>, MockOracle)>;
+    // `MockOracle` agrees with the first oracle, the second one is an outlier
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::from_float(0.5)));
+    // only `MockOracle` has a price for the core asset
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    assert_eq!(Oracle::price(9876), None);
+
+    // with two prices the mean is used
+    type TwoOracles = MedianOracle<(ScaledOracle<50>, ScaledOracle<100>)>;
+    assert_eq!(TwoOracles::price(TEST_ASSET_ID), Some(Price::from_float(0.75)));
+}
+
+#[test]
+fn weight_is_scaled_for_buy_and_refund() {
+    use crate::inspect::EstimateFee;
+
+    /// Scales weight by 1.5.
+    struct OneAndAHalf;
+    impl Convert<Weight, Weight> for OneAndAHalf {
+        fn convert(weight: Weight) -> Weight {
+            weight.saturating_mul(3) / 2
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type WeightScale = OneAndAHalf;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // 1_000_000 weight is priced as 1_500_000
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 2_000_000).into()));
+    let expected_unused: MultiAsset = (Concrete(core_id.clone()), 500_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+    assert_eq!(Trader::estimate_fee(1_000_000, &core_id), Some(1_500_000));
+
+    // refunding 200_000 weight returns the fee for 300_000
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 300_000).into();
+    assert_eq!(trader.refund_weight(200_000), Some(expected_refund));
+
+    // the remaining scaled weight is refunded in full
+    let expected_refund: MultiAsset = (Concrete(core_id), 1_200_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+}
+
+#[test]
+fn fallback_oracle_uses_secondary_if_primary_has_no_price() {
+    use crate::oracles::FallbackOracle;
+
+    /// Secondary oracle pricing `CORE_ASSET_ID` differently than `MockOracle`, and one more asset.
+    struct SecondaryOracle;
+    impl NativePriceOracle<AssetId, Price> for SecondaryOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                CORE_ASSET_ID => Some(Price::saturating_from_integer(2)),
+                9876 => Some(Price::saturating_from_integer(3)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = FallbackOracle<MockOracle, SecondaryOracle>;
+    // primary has it
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    // primary is missing it, secondary has it
+    assert_eq!(Oracle::price(9876), Some(Price::saturating_from_integer(3)));
+    // both are missing it
+    assert_eq!(Oracle::price(1), None);
+}
+
+#[test]
+fn fee_options_list_priced_assets() {
+    use crate::inspect::FeeOption;
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+    let unknown_id: MultiLocation = GeneralKey(9876u32.encode().try_into().unwrap()).into();
+
+    let options = Trader::fee_options(&[core_id.clone(), unknown_id, test_id.clone(), cheap_id.clone()], 1_000);
+    assert_eq!(
+        options,
+        vec![
+            FeeOption {
+                location: core_id,
+                price: Price::one(),
+                amount: 1_000,
+            },
+            FeeOption {
+                location: test_id,
+                price: Price::from_float(0.5),
+                amount: 500,
+            },
+            FeeOption {
+                location: cheap_id,
+                price: Price::saturating_from_integer(4),
+                amount: 4_000,
+            },
+        ]
+    );
+}
+
+#[test]
+fn surcharge_is_applied_to_non_native_assets() {
+    use frame_support::parameter_types;
+
+    parameter_types! {
+        pub NativeLocation: Option<MultiLocation> = MockConvert::convert(CORE_ASSET_ID);
+        pub TenPercent: Price = Price::saturating_from_rational(1, 10);
+        pub HugeSurcharge: Price = Price::from_inner(u128::MAX);
+    }
+
+    struct SurchargedConfig<Surcharge>(PhantomData<Surcharge>);
+    trader_config! {
+        impl<Surcharge: Get<Price>> TraderConfig<AssetId, Balance, Price> for SurchargedConfig<Surcharge> {
+            type NativeAssetLocation = NativeLocation;
+            type Surcharge = Surcharge;
+        }
+    }
+
+    type SurchargedTrader<Surcharge> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        SurchargedConfig<Surcharge>,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = SurchargedTrader::<TenPercent>::new();
+
+    // 10% on top of 0.5 * weight
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 600_000).into()));
+    let expected_unused: MultiAsset = (Concrete(test_id.clone()), 50_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+
+    // the native asset is not surcharged
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id), 1_000_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+
+    // the refund includes the surcharge
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 550_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+
+    // the surcharge can overflow
+    let mut trader = SurchargedTrader::<HugeSurcharge>::new();
+    let res = dbg!(trader.buy_weight(1_000, (Concrete(test_id), 1_000).into()));
+    assert_eq!(res, Err(XcmError::Overflow));
+}
+
+#[cfg(feature = "accounting-checks")]
+#[test]
+#[should_panic(expected = "refunded more than charged")]
+fn refunding_more_than_charged_is_detected() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert!(trader
+        .buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into())
+        .is_ok());
+    assert!(trader.refund_weight(500_000).is_some());
+
+    // force an accounting error
+    trader.refunded.insert(core_id, 1_000_001);
+    drop(trader);
+}
+
+#[test]
+fn fee_is_rounded_up_if_enabled() {
+    use frame_support::traits::ConstBool;
+
+    struct RoundingConfig<RoundFeeUp>(PhantomData<RoundFeeUp>);
+    trader_config! {
+        impl<RoundFeeUp: Get<bool>> TraderConfig<AssetId, Balance, Price> for RoundingConfig<RoundFeeUp> {
+            type RoundFeeUp = RoundFeeUp;
+        }
+    }
+
+    type RoundingTrader<RoundFeeUp> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        RoundingConfig<RoundFeeUp>,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // 0.5 * 1_001 = 500.5 is truncated by default
+    let mut trader = RoundingTrader::<()>::new();
+    let res = dbg!(trader.buy_weight(1_001, (Concrete(test_id.clone()), 1_000).into()));
+    let expected_unused: MultiAsset = (Concrete(test_id.clone()), 500).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+
+    let mut trader = RoundingTrader::<ConstBool<true>>::new();
+    let res = dbg!(trader.buy_weight(1_001, (Concrete(test_id.clone()), 1_000).into()));
+    let expected_unused: MultiAsset = (Concrete(test_id.clone()), 499).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+
+    // the refund is rounded down
+    let expected_refund: MultiAsset = (Concrete(test_id), 500).into();
+    assert_eq!(trader.refund_weight(1_001), Some(expected_refund));
+}
+
+#[test]
+fn minimum_fee_is_charged() {
+    use frame_support::traits::ConstU128;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type MinimumFee = ConstU128<100>;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // 0.5 * 10 is below the minimum
+    let res = dbg!(trader.buy_weight(10, (Concrete(test_id.clone()), 150).into()));
+    let expected_unused: MultiAsset = (Concrete(test_id.clone()), 50).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+
+    // fees above the minimum are unaffected
+    let res = dbg!(trader.buy_weight(1_000, (Concrete(test_id.clone()), 500).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == 0.5 * weight")
+        .is_empty());
+
+    // the payment does not cover the minimum
+    let res = dbg!(trader.buy_weight(10, (Concrete(test_id), 99).into()));
+    assert_eq!(res, Err(XcmError::TooExpensive));
+}
+
+#[test]
+fn fee_above_maximum_is_rejected() {
+    use frame_support::parameter_types;
+
+    parameter_types! {
+        pub MaxFee: Option<Balance> = Some(1_000_000);
+    }
+
+    /// Oracle reporting the prices of `MockOracle` with a spike for `CHEAP_ASSET_ID`.
+    struct SpikingOracle;
+    impl NativePriceOracle<AssetId, Price> for SpikingOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                CHEAP_ASSET_ID => Some(Price::saturating_from_integer(1_000)),
+                _ => MockOracle::price(currency),
+            }
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type MaxFee = MaxFee;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, SpikingOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id), 500_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == 0.5 * weight")
+        .is_empty());
+
+    // the spiked price would charge 1_000_000_000
+    let res = dbg!(trader.buy_weight(1_000_000, (Concrete(cheap_id), u128::MAX).into()));
+    assert_eq!(res, Err(XcmError::TooExpensive));
+}
+
+#[test]
+fn proof_size_is_charged_and_refunded() {
+    use frame_support::weights::Weight as WeightV2;
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // the fee covers both reference time and proof size
+    let res = dbg!(trader.buy_weight_v2(
+        WeightV2::from_parts(1_000_000, 500_000),
+        (Concrete(core_id.clone()), 2_000_000).into()
+    ));
+    let expected_unused: MultiAsset = (Concrete(core_id.clone()), 500_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+    assert_eq!(trader.weight, WeightV2::from_parts(1_000_000, 500_000));
+
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 500_000).into();
+    assert_eq!(
+        trader.refund_weight_v2(WeightV2::from_parts(400_000, 100_000)),
+        Some(expected_refund)
+    );
+    assert_eq!(trader.weight, WeightV2::from_parts(600_000, 400_000));
+
+    // each dimension is capped at the remaining weight
+    let expected_refund: MultiAsset = (Concrete(core_id), 700_000).into();
+    assert_eq!(
+        trader.refund_weight_v2(WeightV2::from_parts(300_000, 1_000_000)),
+        Some(expected_refund)
+    );
+    assert_eq!(trader.weight, WeightV2::from_parts(300_000, 0));
+}
+
+#[test]
+fn proof_size_is_priced_separately() {
+    use frame_support::traits::ConstU128;
+    use frame_support::weights::{ConstantMultiplier, Weight as WeightV2};
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ConvertProofSizeToFee = ConstantMultiplier<Balance, ConstU128<10>>;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // 1_000 for the reference time and 10 * 100 for the proof size
+    let res = dbg!(trader.buy_weight_v2(
+        WeightV2::from_parts(1_000, 100),
+        (Concrete(core_id.clone()), 2_000).into()
+    ));
+    assert!(res
+        .expect("buy_weight should succeed because payment == ref_time + 10 * proof_size")
+        .is_empty());
+
+    // the price is applied to the sum
+    let res = dbg!(trader.buy_weight_v2(WeightV2::from_parts(1_000, 100), (Concrete(test_id), 1_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == 0.5 * (ref_time + 10 * proof_size)")
+        .is_empty());
+
+    // without proof size only the reference time is charged
+    let res = dbg!(trader.buy_weight(1_000, (Concrete(core_id), 1_000).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+}
+
+#[test]
+fn overflow_policy_decides_between_error_and_saturation() {
+    use frame_support::traits::ConstU128;
+    use frame_support::weights::ConstantMultiplier;
+
+    struct PolicyConfig<OverflowPolicy>(PhantomData<OverflowPolicy>);
+    trader_config! {
+        impl<OverflowPolicy: HandleOverflow> TraderConfig<AssetId, Balance, Price> for PolicyConfig<OverflowPolicy> {
+            type OverflowPolicy = OverflowPolicy;
+        }
+    }
+
+    type PolicyTrader<OverflowPolicy> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        ConstantMultiplier<u128, ConstU128<{ Balance::MAX }>>,
+        MockOracle,
+        MockConvert,
+        (),
+        PolicyConfig<OverflowPolicy>,
+    >;
+
+    let overflow_id = MockConvert::convert(OVERFLOW_ASSET_ID).unwrap();
+    let payment: MultiAsset = (Concrete(overflow_id), u128::MAX - 1).into();
+
+    let mut trader = PolicyTrader::<AbortOnOverflow>::new();
+    let res = dbg!(trader.buy_weight(1_000, payment.clone().into()));
+    assert_eq!(res, Err(XcmError::Overflow));
+
+    // the saturated fee cannot be paid
+    let mut trader = PolicyTrader::<SaturateOnOverflow>::new();
+    let res = dbg!(trader.buy_weight(1_000, payment.into()));
+    assert_eq!(res, Err(XcmError::TooExpensive));
+}
+
+#[test]
+fn partial_refund_with_high_price_is_proportional() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let overflow_id = MockConvert::convert(OVERFLOW_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    let weight = 1_000_000_000;
+    let paid = 2_147_483_647 * weight as u128;
+    let res = dbg!(trader.buy_weight(weight, (Concrete(overflow_id.clone()), paid).into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == price * weight")
+        .is_empty());
+
+    let expected_refund: MultiAsset = (Concrete(overflow_id), paid / 4).into();
+    assert_eq!(trader.refund_weight(weight / 4), Some(expected_refund));
+}
+
+#[test]
+fn refunding_all_weight_leaves_no_dust() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        // 0.5 * 1_001 = 500.5 which is truncated to 500
+        let test_payment: MultiAsset = (Concrete(test_id.clone()), 500).into();
+        let res = dbg!(trader.buy_weight(1_001, test_payment.into()));
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        // 0.5 * 1 = 0.5 which is rounded up to 1
+        assert_eq!(trader.refund_weight(1), Some((Concrete(test_id.clone()), 1).into()));
+        assert_eq!(trader.refund_weight(1_000), Some((Concrete(test_id), 499).into()));
+        assert!(trader.paid_assets.is_empty());
+    }
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn refunds_all_assets_proportionally() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+
+        let res = dbg!(trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into()));
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        let res = dbg!(trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into()));
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        // a quarter of the weight refunds a quarter of each asset
+        let expected_refunds: Vec<MultiAsset> = vec![
+            (Concrete(core_id.clone()), 250_000).into(),
+            (Concrete(test_id.clone()), 125_000).into(),
+        ];
+        assert_eq!(trader.refund_weight_all(500_000), expected_refunds);
+
+        // refunding the remaining weight drains all assets
+        let expected_refunds: Vec<MultiAsset> =
+            vec![(Concrete(core_id), 750_000).into(), (Concrete(test_id), 375_000).into()];
+        assert_eq!(trader.refund_weight_all(2_000_000), expected_refunds);
+        assert!(trader.paid_assets.is_empty());
+        assert!(trader.refund_weight_all(1_000).is_empty());
+    }
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn most_recent_payment_is_refunded_first_if_enabled() {
+    use frame_support::traits::ConstBool;
+
+    struct LifoConfig<LifoRefunds>(PhantomData<LifoRefunds>);
+    trader_config! {
+        impl<LifoRefunds: Get<bool>> TraderConfig<AssetId, Balance, Price> for LifoConfig<LifoRefunds> {
+            type LifoRefunds = LifoRefunds;
+        }
+    }
+
+    type LifoTrader<LifoRefunds> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        LifoConfig<LifoRefunds>,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    fn buy(trader: &mut impl WeightTrader, core_id: &MultiLocation, test_id: &MultiLocation) {
+        let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+    }
+
+    // by default the assets are refunded in order of their locations
+    let mut trader = LifoTrader::<()>::new();
+    buy(&mut trader, &core_id, &test_id);
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 1_000_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+
+    // the asset paid last is refunded first
+    let mut trader = LifoTrader::<ConstBool<true>>::new();
+    buy(&mut trader, &core_id, &test_id);
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 500_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+    let expected_refund: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+}
+
+thread_local! {
+    pub static REVENUE_HOOK_CALLS: RefCell<Vec<(MultiAsset, Option<DispatchError>)>> = RefCell::new(Vec::new());
+}
+
+/// Revenue hook recording all calls, with the error for failed deposits.
+struct RecordingHook;
+impl OnRevenue for RecordingHook {
+    fn on_taken(asset: &MultiAsset) {
+        REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().push((asset.clone(), None)));
+    }
+
+    fn on_deposit_failed(asset: &MultiAsset, error: DispatchError) {
+        REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().push((asset.clone(), Some(error))));
+    }
+}
+
+#[test]
+fn revenue_hook_is_called_on_deposit_and_failure() {
+    use frame_support::traits::Everything;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    struct FailingDeposit;
+    impl DepositFee<AccountId, AssetId, Balance> for FailingDeposit {
+        fn deposit_fee(_who: &AccountId, _asset: AssetId, _amount: Balance) -> DispatchResult {
+            Err("deposit failed".into())
+        }
+    }
+
+    type Revenue<D> = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        D,
+        MockFeeReceiver,
+        Everything,
+        (),
+        RecordingHook,
+    >;
+
+    ExpectDeposit::reset();
+    REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let revenue: MultiAsset = (core_id, 1234).into();
+
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1234);
+    Revenue::<ExpectDeposit>::take_revenue(revenue.clone());
+    assert_that_fee_is_deposited!();
+
+    Revenue::<FailingDeposit>::take_revenue(revenue.clone());
+
+    REVENUE_HOOK_CALLS.with(|c| {
+        assert_eq!(
+            *c.borrow(),
+            vec![(revenue.clone(), None), (revenue, Some("deposit failed".into()))]
+        )
+    });
+}
+
+#[test]
+fn revenue_hook_is_called_on_drop() {
+    use frame_support::traits::Everything;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    struct FailingDeposit;
+    impl DepositFee<AccountId, AssetId, Balance> for FailingDeposit {
+        fn deposit_fee(_who: &AccountId, _asset: AssetId, _amount: Balance) -> DispatchResult {
+            Err("deposit failed".into())
+        }
+    }
+
+    type Revenue<D> = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        D,
+        MockFeeReceiver,
+        Everything,
+        (),
+        RecordingHook,
+    >;
+
+    type Trader<D> =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, Revenue<D>>;
+
+    ExpectDeposit::reset();
+    REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+
+    {
+        let mut trader = Trader::<ExpectDeposit>::new();
+        let res = trader.buy_weight(1_000_000, payment.clone().into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1_000_000);
+    }
+    assert_that_fee_is_deposited!();
+    REVENUE_HOOK_CALLS.with(|c| assert_eq!(*c.borrow(), vec![(payment.clone(), None)]));
+
+    // revenue that could not be deposited is not reported as taken
+    REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().clear());
+    {
+        let mut trader = Trader::<FailingDeposit>::new();
+        let res = trader.buy_weight(1_000_000, payment.clone().into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+    }
+    REVENUE_HOOK_CALLS.with(|c| assert_eq!(*c.borrow(), vec![(payment, Some("deposit failed".into()))]));
+}
+
+#[test]
+fn revenue_hook_is_called_on_trap() {
+    use frame_support::traits::Everything;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        RecordingHook,
+    >;
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, Revenue>;
+
+    ExpectDeposit::reset();
+    REVENUE_HOOK_CALLS.with(|c| c.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+
+    {
+        let mut trader = Trader::new();
+        let res = trader.buy_weight(1_000_000, payment.clone().into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1_000_000);
+        trader.emit_on_trap();
+        assert_that_fee_is_deposited!();
+        REVENUE_HOOK_CALLS.with(|c| assert_eq!(*c.borrow(), vec![(payment.clone(), None)]));
+    }
+
+    // the revenue is neither taken nor reported again on drop
+    assert_that_fee_is_deposited!();
+    REVENUE_HOOK_CALLS.with(|c| assert_eq!(*c.borrow(), vec![(payment, None)]));
+}
+
+thread_local! {
+    pub static BURNT_FEES: RefCell<Vec<(AssetId, Balance)>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn revenue_is_burnt() {
+    use crate::revenue::{BurnFee, BurnRevenue};
+
+    struct MockBurner;
+    impl BurnFee<AssetId, Balance> for MockBurner {
+        fn burn_fee(asset: AssetId, amount: Balance) -> DispatchResult {
+            BURNT_FEES.with(|b| b.borrow_mut().push((asset, amount)));
+            Ok(())
+        }
+    }
+
+    type Revenue = BurnRevenue<AssetId, Balance, MockConvert, MockBurner>;
+
+    BURNT_FEES.with(|b| b.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    Revenue::take_revenue((core_id.clone(), 1234).into());
+    // non-fungible assets cannot be burnt
+    Revenue::take_revenue((core_id, NonFungible(AssetInstance::Index(1))).into());
+
+    BURNT_FEES.with(|b| assert_eq!(*b.borrow(), vec![(CORE_ASSET_ID, 1234)]));
+}
+
+#[test]
+fn revenue_is_split_by_ratio() {
+    use crate::revenue::SplitRevenue;
+    use frame_support::parameter_types;
+    use sp_runtime::Permill;
+
+    parameter_types! {
+        pub const ThirtyPercent: Permill = Permill::from_percent(30);
+    }
+
+    type Revenue = SplitRevenue<ExpectRevenue, OtherRevenue, ThirtyPercent>;
+
+    ExpectRevenue::reset();
+    OTHER_REVENUE.with(|r| r.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    // 70% of 1_001 is 700.7, the dust goes to the first receiver
+    Revenue::take_revenue((core_id.clone(), 1_001).into());
+
+    ExpectRevenue::register_expected_asset((core_id.clone(), 301).into());
+    ExpectRevenue::expect_revenue();
+    let expected_other: MultiAsset = (core_id, 700).into();
+    OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![expected_other]));
+}
+
+#[test]
+fn buffered_revenue_is_deposited_once_threshold_is_reached() {
+    use crate::revenue::BufferedRevenue;
+    use frame_support::{parameter_types, storage_alias, Twox64Concat};
+
+    #[storage_alias]
+    type RevenueBuffer = StorageMap<Test, Twox64Concat, AssetId, Balance, ValueQuery>;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    parameter_types! {
+        pub const FlushThreshold: Balance = 1_000;
+    }
+
+    type Revenue = BufferedRevenue<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        RevenueBuffer,
+        FlushThreshold,
+    >;
+
+    sp_io::TestExternalities::default().execute_with(|| {
+        ExpectDeposit::reset();
+        let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+        let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+        // below the threshold nothing is deposited (`ExpectDeposit` panics on unexpected deposits)
+        Revenue::take_revenue((core_id.clone(), 400).into());
+        Revenue::take_revenue((core_id.clone(), 500).into());
+        Revenue::take_revenue((test_id, 10).into());
+        assert_eq!(RevenueBuffer::get(CORE_ASSET_ID), 900);
+        assert_eq!(RevenueBuffer::get(TEST_ASSET_ID), 10);
+
+        // crossing the threshold deposits everything accumulated at once
+        ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1_100);
+        Revenue::take_revenue((core_id, 200).into());
+        assert_that_fee_is_deposited!();
+        assert!(!RevenueBuffer::contains_key(CORE_ASSET_ID));
+
+        // flushing deposits the rest regardless of the threshold
+        ExpectDeposit::register_expected_fee(42, TEST_ASSET_ID, 10);
+        Revenue::flush_all();
+        assert_that_fee_is_deposited!();
+        assert!(!RevenueBuffer::contains_key(TEST_ASSET_ID));
+    });
+}
+
+#[test]
+fn buffered_revenue_passes_on_unconvertible_assets() {
+    use crate::revenue::BufferedRevenue;
+    use frame_support::{parameter_types, storage_alias, Twox64Concat};
+
+    #[storage_alias]
+    type RevenueBuffer = StorageMap<Test, Twox64Concat, AssetId, Balance, ValueQuery>;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    parameter_types! {
+        pub const FlushThreshold: Balance = 1_000;
+    }
+
+    type Revenue = BufferedRevenue<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        RevenueBuffer,
+        FlushThreshold,
+        ExpectRevenue,
+    >;
+
+    sp_io::TestExternalities::default().execute_with(|| {
+        ExpectRevenue::reset();
+        let unknown = MultiLocation::new(1, X1(Parachain(3_000)));
+        let asset: MultiAsset = (unknown, 400).into();
+
+        ExpectRevenue::register_expected_asset(asset.clone());
+        Revenue::take_revenue(asset);
+        ExpectRevenue::expect_revenue();
+        assert_eq!(RevenueBuffer::iter().count(), 0);
+    });
+}
+
+#[test]
+fn flush_revenue_takes_revenue_before_drop() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    let payment: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    let res = dbg!(trader.buy_weight(1_000_000, payment.clone().into()));
+    assert!(res
+        .expect("buy_weight should succeed because payment == weight")
+        .is_empty());
+
+    trader.flush_revenue();
+    ExpectRevenue::register_expected_asset(payment);
+    ExpectRevenue::expect_revenue();
+
+    // dropping after flushing does not take the revenue again
+    ExpectRevenue::reset();
+    drop(trader);
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn peek_fee_does_not_change_state() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    let payment: MultiAsset = (Concrete(core_id.clone()), 1_000_000).into();
+    assert!(trader.buy_weight(500_000, payment.into()).is_ok());
+    let paid_assets = trader.paid_assets.clone();
+    let weight = trader.weight;
+
+    let payment: Assets = MultiAsset::from((Concrete(test_id.clone()), 1_000_000)).into();
+    assert_eq!(
+        trader.peek_fee(WeightV2::from_ref_time(1_000_000), &payment),
+        Ok((test_id.clone(), 500_000))
+    );
+    assert_eq!(
+        trader.peek_fee(WeightV2::from_ref_time(10_000_000), &payment),
+        Err(XcmError::TooExpensive)
+    );
+
+    assert_eq!(trader.paid_assets, paid_assets);
+    assert_eq!(trader.weight, weight);
+    assert_eq!(trader.buys(), 1);
+
+    // buying afterwards charges what was peeked
+    let res = trader.buy_weight(1_000_000, payment);
+    let expected_unused: Assets = MultiAsset::from((Concrete(test_id), 500_000)).into();
+    assert_eq!(res, Ok(expected_unused));
+}
+
+#[test]
+fn getters_reflect_accumulated_state() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+    assert_eq!(trader.paid_assets().count(), 0);
+
+    let payment: MultiAsset = (Concrete(core_id.clone()), 1_000_000).into();
+    assert!(trader.buy_weight(1_000_000, payment.into()).is_ok());
+    let payment: MultiAsset = (Concrete(test_id.clone()), 1_000_000).into();
+    assert!(trader.buy_weight(1_000_000, payment.into()).is_ok());
+
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(2_000_000));
+    let half = Price::from_float(0.5);
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&core_id, &Price::one(), &1_000_000), (&test_id, &half, &500_000)]
+    );
+}
+
+#[test]
+fn versioned_locations_resolve_to_the_same_asset() {
+    use crate::location::VersionedLocationConverter;
+    use polkadot_xcm::{v0, VersionedMultiLocation};
+
+    type Converter = VersionedLocationConverter<MockConvert>;
+
+    let key = TEST_ASSET_ID.encode();
+    let v0_location = v0::MultiLocation::X1(v0::Junction::GeneralKey(key.clone().try_into().unwrap()));
+    let latest_location = MultiLocation::new(0, X1(GeneralKey(key.try_into().unwrap())));
+
+    assert_eq!(
+        Converter::convert(VersionedMultiLocation::V0(v0_location)),
+        Some(TEST_ASSET_ID)
+    );
+    assert_eq!(
+        Converter::convert(VersionedMultiLocation::from(latest_location.clone())),
+        Some(TEST_ASSET_ID)
+    );
+    assert_eq!(Converter::convert(latest_location.clone()), Some(TEST_ASSET_ID));
+
+    let unknown = MultiLocation::new(1, X1(Parachain(1_000)));
+    assert_eq!(Converter::convert(VersionedMultiLocation::from(unknown)), None);
+
+    // the converter can be used as the `ConvertCurrency` of the trader
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, Converter, ()>;
+
+    let mut trader = Trader::new();
+    let payment: MultiAsset = (Concrete(latest_location.clone()), 500_000).into();
+    let res = trader.buy_weight(1_000_000, payment.into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&latest_location, &Price::from_float(0.5), &500_000)]
+    );
+}
+
+#[cfg(feature = "orml")]
+mod orml_deposit {
+    use crate::deposit::OrmlTokensDepositFee;
+    use frame_support::{
+        parameter_types,
+        traits::{Everything, Nothing},
+    };
+    use orml_traits::{parameter_type_with_key, MultiCurrency};
+    use pallet_transaction_multi_payment::DepositFee;
+    use sp_core::H256;
+    use sp_runtime::testing::Header;
+    use sp_runtime::traits::{BlakeTwo256, Identity, IdentityLookup};
+
+    type AccountId = u64;
+    type AssetId = u32;
+    type Balance = u128;
+    type Amount = i128;
+
+    type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+        {
+            System: frame_system,
+            Tokens: orml_tokens,
+        }
+    );
+
+    parameter_types! {
+        pub const BlockHashCount: u64 = 250;
+    }
+
+    impl frame_system::Config for Test {
+        type BaseCallFilter = Everything;
+        type BlockWeights = ();
+        type BlockLength = ();
+        type Origin = Origin;
+        type Call = Call;
+        type Index = u64;
+        type BlockNumber = u64;
+        type Hash = H256;
+        type Hashing = BlakeTwo256;
+        type AccountId = AccountId;
+        type Lookup = IdentityLookup<Self::AccountId>;
+        type Header = Header;
+        type Event = Event;
+        type BlockHashCount = BlockHashCount;
+        type DbWeight = ();
+        type Version = ();
+        type PalletInfo = PalletInfo;
+        type AccountData = ();
+        type OnNewAccount = ();
+        type OnKilledAccount = ();
+        type SystemWeightInfo = ();
+        type SS58Prefix = ();
+        type OnSetCode = ();
+        type MaxConsumers = frame_support::traits::ConstU32<16>;
+    }
+
+    parameter_type_with_key! {
+        pub ExistentialDeposits: |_currency_id: AssetId| -> Balance {
+            10u128
+        };
+    }
+
+    impl orml_tokens::Config for Test {
+        type Event = Event;
+        type Balance = Balance;
+        type Amount = Amount;
+        type CurrencyId = AssetId;
+        type WeightInfo = ();
+        type ExistentialDeposits = ExistentialDeposits;
+        type OnDust = ();
+        type MaxLocks = ();
+        type DustRemovalWhitelist = Nothing;
+        type OnNewTokenAccount = ();
+        type OnKilledTokenAccount = ();
+        type ReserveIdentifier = ();
+        type MaxReserves = ();
+    }
+
+    type Deposit = OrmlTokensDepositFee<Test, Identity>;
+
+    fn new_test_ext() -> sp_io::TestExternalities {
+        frame_system::GenesisConfig::default()
+            .build_storage::<Test>()
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn fee_is_deposited_to_receiver() {
+        new_test_ext().execute_with(|| {
+            assert_eq!(Deposit::deposit_fee(&42, 1, 1_000), Ok(()));
+            assert_eq!(Tokens::free_balance(1, &42), 1_000);
+        });
+    }
+
+    #[test]
+    fn deposit_below_existential_deposit_fails() {
+        new_test_ext().execute_with(|| {
+            assert_eq!(
+                Deposit::deposit_fee(&42, 1, 5),
+                Err(orml_tokens::Error::<Test>::ExistentialDeposit.into())
+            );
+            assert_eq!(Tokens::free_balance(1, &42), 0);
+        });
+    }
+}
+
+#[cfg(feature = "pallet-assets")]
+mod pallet_assets_deposit {
+    use crate::deposit::PalletAssetsDepositFee;
+    use frame_support::{
+        assert_ok, parameter_types,
+        traits::{AsEnsureOriginWithArg, ConstU128, ConstU32, Everything},
+    };
+    use frame_system::{EnsureRoot, EnsureSigned};
+    use pallet_transaction_multi_payment::DepositFee;
+    use sp_core::H256;
+    use sp_runtime::testing::Header;
+    use sp_runtime::traits::{BlakeTwo256, IdentityLookup};
+
+    type AccountId = u64;
+    type AssetId = u32;
+    type Balance = u128;
+
+    type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    const ASSET_OWNER: AccountId = 1;
+    const ASSET: AssetId = 1;
+
+    frame_support::construct_runtime!(
+        pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+        {
+            System: frame_system,
+            Balances: pallet_balances,
+            Assets: pallet_assets,
+        }
+    );
+
+    parameter_types! {
+        pub const BlockHashCount: u64 = 250;
+    }
+
+    impl frame_system::Config for Test {
+        type BaseCallFilter = Everything;
+        type BlockWeights = ();
+        type BlockLength = ();
+        type Origin = Origin;
+        type Call = Call;
+        type Index = u64;
+        type BlockNumber = u64;
+        type Hash = H256;
+        type Hashing = BlakeTwo256;
+        type AccountId = AccountId;
+        type Lookup = IdentityLookup<Self::AccountId>;
+        type Header = Header;
+        type Event = Event;
+        type BlockHashCount = BlockHashCount;
+        type DbWeight = ();
+        type Version = ();
+        type PalletInfo = PalletInfo;
+        type AccountData = pallet_balances::AccountData<Balance>;
+        type OnNewAccount = ();
+        type OnKilledAccount = ();
+        type SystemWeightInfo = ();
+        type SS58Prefix = ();
+        type OnSetCode = ();
+        type MaxConsumers = ConstU32<16>;
+    }
+
+    impl pallet_balances::Config for Test {
+        type MaxLocks = ();
+        type Balance = Balance;
+        type Event = Event;
+        type DustRemoval = ();
+        type ExistentialDeposit = ConstU128<1>;
+        type AccountStore = System;
+        type WeightInfo = ();
+        type MaxReserves = ();
+        type ReserveIdentifier = ();
+    }
+
+    impl pallet_assets::Config for Test {
+        type Event = Event;
+        type Balance = Balance;
+        type AssetId = AssetId;
+        type AssetIdParameter = AssetId;
+        type Currency = Balances;
+        type CreateOrigin = AsEnsureOriginWithArg<EnsureSigned<AccountId>>;
+        type ForceOrigin = EnsureRoot<AccountId>;
+        type AssetDeposit = ConstU128<1>;
+        type AssetAccountDeposit = ConstU128<1>;
+        type MetadataDepositBase = ConstU128<1>;
+        type MetadataDepositPerByte = ConstU128<1>;
+        type ApprovalDeposit = ConstU128<1>;
+        type StringLimit = ConstU32<50>;
+        type Freezer = ();
+        type Extra = ();
+        type RemoveItemsLimit = ConstU32<5>;
+        type WeightInfo = ();
+    }
+
+    type Deposit = PalletAssetsDepositFee<Test>;
+
+    /// Build externalities with a sufficient `ASSET` (minimum balance 10) owned by `ASSET_OWNER`.
+    fn new_test_ext() -> sp_io::TestExternalities {
+        let mut ext: sp_io::TestExternalities = frame_system::GenesisConfig::default()
+            .build_storage::<Test>()
+            .unwrap()
+            .into();
+        ext.execute_with(|| {
+            assert_ok!(Assets::force_create(Origin::root(), ASSET, ASSET_OWNER, true, 10));
+        });
+        ext
+    }
+
+    #[test]
+    fn fee_is_deposited_to_receiver() {
+        new_test_ext().execute_with(|| {
+            assert_eq!(Deposit::deposit_fee(&42, ASSET, 1_000), Ok(()));
+            assert_eq!(Assets::balance(ASSET, 42), 1_000);
+        });
+    }
+
+    #[test]
+    fn deposit_of_frozen_asset_fails() {
+        new_test_ext().execute_with(|| {
+            assert_ok!(Assets::freeze_asset(Origin::signed(ASSET_OWNER), ASSET));
+
+            assert_eq!(
+                Deposit::deposit_fee(&42, ASSET, 1_000),
+                Err(pallet_assets::Error::<Test>::Frozen.into())
+            );
+            assert_eq!(Assets::balance(ASSET, 42), 0);
+        });
+    }
+
+    #[test]
+    fn deposit_below_minimum_balance_fails() {
+        new_test_ext().execute_with(|| {
+            assert!(Deposit::deposit_fee(&42, ASSET, 5).is_err());
+            assert_eq!(Assets::balance(ASSET, 42), 0);
+        });
+    }
+}
+
+#[test]
+fn revenue_is_swapped_to_native() {
+    use crate::revenue::{RevenueRouter, SwapToNative};
+    use frame_support::traits::ConstU32;
+
+    ExpectDeposit::reset();
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    /// Sells the test asset for twice the amount of native, fails for everything else.
+    struct MockRouter;
+    impl RevenueRouter<AssetId, Balance> for MockRouter {
+        fn sell(asset_in: AssetId, asset_out: AssetId, amount_in: Balance) -> Result<Balance, DispatchError> {
+            assert_eq!(asset_out, CORE_ASSET_ID);
+            match asset_in {
+                TEST_ASSET_ID => Ok(amount_in * 2),
+                _ => Err("no route".into()),
+            }
+        }
+    }
+
+    type Revenue = SwapToNative<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        MockRouter,
+        ConstU32<CORE_ASSET_ID>,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    // swapped to native
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 2_000);
+    Revenue::take_revenue((test_id, 1_000).into());
+    assert_that_fee_is_deposited!();
+
+    // the swap fails, so the original asset is deposited
+    ExpectDeposit::register_expected_fee(42, CHEAP_ASSET_ID, 1_000);
+    Revenue::take_revenue((cheap_id, 1_000).into());
+    assert_that_fee_is_deposited!();
+
+    // native revenue is not swapped
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1_000);
+    Revenue::take_revenue((core_id, 1_000).into());
+    assert_that_fee_is_deposited!();
+}
+
+#[test]
+fn spot_price_oracle_prices_assets_with_native_pool() {
+    use crate::oracles::SpotPriceOracle;
+    use frame_support::traits::ConstU32;
+    use hydradx_traits::pools::SpotPriceProvider;
+
+    /// AMM with a single pool of the core and the test asset, with 2 test asset per core asset.
+    struct MockAmm;
+    impl SpotPriceProvider<AssetId> for MockAmm {
+        type Price = Price;
+
+        fn pair_exists(asset_a: AssetId, asset_b: AssetId) -> bool {
+            Self::spot_price(asset_a, asset_b).is_some()
+        }
+
+        fn spot_price(asset_a: AssetId, asset_b: AssetId) -> Option<Price> {
+            match (asset_a, asset_b) {
+                (CORE_ASSET_ID, TEST_ASSET_ID) => Some(Price::saturating_from_integer(2)),
+                (TEST_ASSET_ID, CORE_ASSET_ID) => Some(Price::saturating_from_rational(1, 2)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = SpotPriceOracle<MockAmm, ConstU32<CORE_ASSET_ID>>;
+    // priced pair
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::saturating_from_integer(2)));
+    // no pool
+    assert_eq!(Oracle::price(CHEAP_ASSET_ID), None);
+    // native asset
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+}
+
+#[test]
+fn inverted_oracle_returns_reciprocal_price() {
+    use crate::oracles::InvertedOracle;
+
+    struct ZeroOracle;
+    impl NativePriceOracle<AssetId, Price> for ZeroOracle {
+        fn price(_currency: AssetId) -> Option<Price> {
+            Some(Price::zero())
+        }
+    }
+
+    type Oracle = InvertedOracle<MockOracle>;
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::saturating_from_integer(2)));
+    assert_eq!(
+        Oracle::price(CHEAP_ASSET_ID),
+        Some(Price::saturating_from_rational(1, 4))
+    );
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    assert_eq!(Oracle::price(9876), None);
+    // no division by zero
+    assert_eq!(InvertedOracle::<ZeroOracle>::price(TEST_ASSET_ID), None);
+    // inverting twice round-trips
+    assert_eq!(
+        InvertedOracle::<InvertedOracle<MockOracle>>::price(TEST_ASSET_ID),
+        MockOracle::price(TEST_ASSET_ID)
+    );
+}
+
+thread_local! {
+    pub static RAW_PRICE: RefCell<Option<Price>> = RefCell::new(None);
+    pub static BLOCK_NUMBER: RefCell<u64> = RefCell::new(1);
+}
+
+#[test]
+fn ema_oracle_lags_behind_price_changes() {
+    use crate::oracles::EmaOracle;
+    use frame_support::{parameter_types, storage_alias, Twox64Concat};
+    use sp_runtime::traits::BlockNumberProvider;
+    use sp_runtime::Permill;
+
+    #[storage_alias]
+    type EmaPrices = StorageMap<Test, Twox64Concat, AssetId, (Price, u64)>;
+
+    struct RawOracle;
+    impl NativePriceOracle<AssetId, Price> for RawOracle {
+        fn price(_currency: AssetId) -> Option<Price> {
+            RAW_PRICE.with(|p| *p.borrow())
+        }
+    }
+
+    struct MockBlockNumber;
+    impl BlockNumberProvider for MockBlockNumber {
+        type BlockNumber = u64;
+
+        fn current_block_number() -> u64 {
+            BLOCK_NUMBER.with(|b| *b.borrow())
+        }
+    }
+
+    parameter_types! {
+        pub const Alpha: Permill = Permill::from_percent(50);
+    }
+
+    type Oracle = EmaOracle<RawOracle, Alpha, EmaPrices, MockBlockNumber>;
+    let set_raw_price = |price: Option<Price>| RAW_PRICE.with(|p| *p.borrow_mut() = price);
+    let next_block = || BLOCK_NUMBER.with(|b| *b.borrow_mut() += 1);
+
+    sp_io::TestExternalities::default().execute_with(|| {
+        set_raw_price(None);
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), None::<Price>);
+        assert_eq!(Oracle::price(TEST_ASSET_ID), None);
+        assert_eq!(EmaPrices::get(TEST_ASSET_ID), None);
+
+        // unobserved assets are priced by the inner oracle, without storing an average
+        set_raw_price(Some(Price::one()));
+        assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::one()));
+        assert_eq!(EmaPrices::get(TEST_ASSET_ID), None);
+
+        // the first observation seeds the average
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), Some(Price::one()));
+
+        // querying the price does not move the average
+        set_raw_price(Some(Price::saturating_from_integer(3)));
+        assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::one()));
+        assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::one()));
+
+        // neither does observing it again in the same block
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), Some(Price::one()));
+
+        // a step change is followed halfway per block
+        next_block();
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), Some(Price::saturating_from_integer(2)));
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), Some(Price::saturating_from_integer(2)));
+        next_block();
+        assert_eq!(
+            Oracle::observe(TEST_ASSET_ID),
+            Some(Price::saturating_from_rational(5, 2))
+        );
+        assert_eq!(
+            Oracle::price(TEST_ASSET_ID),
+            Some(Price::saturating_from_rational(5, 2))
+        );
+
+        // and the same downwards
+        set_raw_price(Some(Price::saturating_from_rational(3, 2)));
+        next_block();
+        assert_eq!(Oracle::observe(TEST_ASSET_ID), Some(Price::saturating_from_integer(2)));
+        assert_eq!(
+            EmaPrices::get(TEST_ASSET_ID),
+            Some((Price::saturating_from_integer(2), 4))
+        );
+    });
+}
+
+#[test]
+fn fallback_trader_routes_buys_and_refunds() {
+    use crate::FallbackTrader;
+
+    struct CoreOnly;
+    impl Contains<AssetId> for CoreOnly {
+        fn contains(id: &AssetId) -> bool {
+            *id == CORE_ASSET_ID
+        }
+    }
+
+    struct TestOnly;
+    impl Contains<AssetId> for TestOnly {
+        fn contains(id: &AssetId) -> bool {
+            *id == TEST_ASSET_ID
+        }
+    }
+
+    struct OnlyConfig<Accepted>(PhantomData<Accepted>);
+    trader_config! {
+        impl<Accepted: Contains<AssetId>> TraderConfig<AssetId, Balance, Price> for OnlyConfig<Accepted> {
+            type AcceptedAssets = Accepted;
+        }
+    }
+
+    type OnlyTrader<Accepted> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        OnlyConfig<Accepted>,
+    >;
+    type Trader = FallbackTrader<OnlyTrader<CoreOnly>, OnlyTrader<TestOnly>>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    // the first trader accepts the core asset
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("the first trader accepts the core asset").is_empty());
+    assert!(trader.b.paid_assets().next().is_none());
+    let expected_refund: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+
+    // the test asset is only accepted by the second trader
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+    assert!(res.expect("the second trader accepts the test asset").is_empty());
+    assert!(trader.a.paid_assets().next().is_none());
+    let expected_refund: MultiAsset = (Concrete(test_id), 500_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+
+    // neither trader accepts the cheap asset
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(cheap_id), 4_000_000).into());
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+    assert_eq!(trader.refund_weight(1_000_000), None);
+}
+
+#[test]
+fn fee_multiplier_scales_charge_and_refund() {
+    use frame_support::parameter_types;
+
+    parameter_types! {
+        pub Double: Price = Price::saturating_from_integer(2);
+    }
+
+    struct MultipliedConfig<FeeMultiplier>(PhantomData<FeeMultiplier>);
+    trader_config! {
+        impl<FeeMultiplier: Get<Price>> TraderConfig<AssetId, Balance, Price> for MultipliedConfig<FeeMultiplier> {
+            type FeeMultiplier = FeeMultiplier;
+        }
+    }
+
+    type MultipliedTrader<FeeMultiplier> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        MultipliedConfig<FeeMultiplier>,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // no multiplier by default
+    let mut trader = MultipliedTrader::<UnitFeeMultiplier>::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+
+    // a multiplier of two doubles the charged amount
+    let mut trader = MultipliedTrader::<Double>::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    // and the refund
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 400_000).into();
+    assert_eq!(trader.refund_weight(400_000), Some(expected_refund));
+    let expected_refund: MultiAsset = (Concrete(test_id), 600_000).into();
+    assert_eq!(trader.refund_weight(600_000), Some(expected_refund));
+}
+
+thread_local! {
+    pub static FEE_RATE_OVERRIDE: RefCell<Option<Price>> = RefCell::new(None);
+}
+
+#[test]
+fn fee_rate_override_is_used_for_buy_and_refund() {
+    /// Overrides the fee rate of the test asset with `FEE_RATE_OVERRIDE`.
+    struct MockFeeRate;
+    impl Convert<AssetId, Option<Price>> for MockFeeRate {
+        fn convert(id: AssetId) -> Option<Price> {
+            match id {
+                TEST_ASSET_ID => FEE_RATE_OVERRIDE.with(|r| *r.borrow()),
+                _ => None,
+            }
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type FeeRateOverride = MockFeeRate;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    FEE_RATE_OVERRIDE.with(|r| *r.borrow_mut() = Some(Price::saturating_from_rational(1, 2)));
+
+    let mut trader = Trader::new();
+    // the core asset is charged at the default rate
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    // the test asset is charged at half the rate: 500_000 native, i.e. 250_000 test asset
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 250_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+
+    // refunds use the rate of the buy, even if it changed in the meantime
+    FEE_RATE_OVERRIDE.with(|r| *r.borrow_mut() = None);
+    let expected_refund: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    assert_eq!(trader.refund_weight(1_000_000), Some(expected_refund));
+    let expected_refund: MultiAsset = (Concrete(test_id), 100_000).into();
+    assert_eq!(trader.refund_weight(400_000), Some(expected_refund));
+}
+
+#[test]
+fn fees_are_scaled_to_the_decimals_of_the_paying_asset() {
+    use frame_support::parameter_types;
+
+    parameter_types! {
+        pub NativeLocation: Option<MultiLocation> = MockConvert::convert(CORE_ASSET_ID);
+    }
+
+    struct MockDecimals;
+    impl Convert<AssetId, Option<u8>> for MockDecimals {
+        fn convert(id: AssetId) -> Option<u8> {
+            match id {
+                CORE_ASSET_ID => Some(12),
+                TEST_ASSET_ID => Some(6),
+                CHEAP_ASSET_ID => Some(18),
+                OVERFLOW_ASSET_ID => Some(50),
+                _ => None,
+            }
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type NativeAssetLocation = NativeLocation;
+            type Decimals = MockDecimals;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+    let overflow_id = MockConvert::convert(OVERFLOW_ASSET_ID).unwrap();
+    let weight = WeightV2::from_ref_time(1_000_000_000_000);
+
+    // the native asset is not scaled
+    assert_eq!(
+        Trader::compute_fee(weight, &core_id, Price::one()),
+        Ok(1_000_000_000_000)
+    );
+    // six decimals less than native
+    assert_eq!(
+        Trader::compute_fee(weight, &test_id, Price::saturating_from_rational(1, 2)),
+        Ok(500_000)
+    );
+    // six decimals more than native
+    assert_eq!(
+        Trader::compute_fee(weight, &cheap_id, Price::saturating_from_integer(4)),
+        Ok(4_000_000_000_000_000_000)
+    );
+    // scaling up by 38 decimals overflows
+    assert_eq!(
+        Trader::compute_fee(weight, &overflow_id, Price::saturating_from_integer(1)),
+        Err(XcmError::Overflow)
+    );
+
+    // refunds are scaled as well
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000_000_000, (Concrete(test_id.clone()), 500_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let expected_refund: MultiAsset = (Concrete(test_id), 200_000).into();
+    assert_eq!(trader.refund_weight(400_000_000_000), Some(expected_refund));
+}
+
+#[test]
+fn zero_prices_are_rejected() {
+    /// Prices the test asset at zero.
+    struct ZeroPriceOracle;
+    impl NativePriceOracle<AssetId, Price> for ZeroPriceOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                TEST_ASSET_ID => Some(Price::zero()),
+                _ => MockOracle::price(currency),
+            }
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, ZeroPriceOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // weight cannot be bought for free
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 1_000).into());
+    assert_eq!(res, Err(XcmError::AssetNotFound));
+
+    // an entry with a zero price is never used for refunds
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    trader.paid_assets.entry(test_id, Price::zero()).amount = 1_000;
+    let expected_refund: MultiAsset = (Concrete(core_id), 400_000).into();
+    assert_eq!(trader.refund_weight(400_000), Some(expected_refund));
+}
+
+#[test]
+fn buying_more_than_max_buy_weight_fails() {
+    use frame_support::traits::ConstU64;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type MaxBuyWeight = ConstU64<1_000_000>;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    // buying exactly the maximum succeeds
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+
+    // buying more fails without charging anything
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_001, (Concrete(core_id), 2_000_000).into());
+    assert_eq!(res, Err(XcmError::WeightLimitReached(1_000_001)));
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+}
+
+thread_local! {
+    pub static PROPTEST_PRICE: RefCell<Price> = RefCell::new(Price::one());
+}
+
+/// Prices the test asset with `PROPTEST_PRICE`.
+struct ProptestOracle;
+impl NativePriceOracle<AssetId, Price> for ProptestOracle {
+    fn price(currency: AssetId) -> Option<Price> {
+        match currency {
+            TEST_ASSET_ID => Some(PROPTEST_PRICE.with(|p| *p.borrow())),
+            _ => MockOracle::price(currency),
+        }
+    }
+}
+
+fn some_price() -> impl proptest::strategy::Strategy<Value = Price> {
+    use proptest::prelude::*;
+    prop_oneof![
+        // fractional prices
+        (1..Price::DIV).prop_map(Price::from_inner),
+        // large prices
+        (1u128..1_000_000_000_000).prop_map(Price::saturating_from_integer),
+    ]
+}
+
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(1000))]
+    #[test]
+    fn full_refund_never_exceeds_payment(
+        weight in 1..1_000_000_000_000u64,
+        price in some_price(),
+        extra in 0..1_000_000_000u128,
+    ) {
+        type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, ProptestOracle, MockConvert, ()>;
+
+        PROPTEST_PRICE.with(|p| *p.borrow_mut() = price);
+        let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+        let fee = Trader::compute_fee(WeightV2::from_ref_time(weight), &test_id, price);
+        proptest::prop_assume!(fee.is_ok());
+        let payment = fee.unwrap().saturating_add(extra);
+        proptest::prop_assume!(payment > 0);
+
+        let mut trader = Trader::new();
+        let unused = trader
+            .buy_weight(weight, (Concrete(test_id.clone()), payment).into())
+            .expect("buy_weight should succeed");
+        let unused = unused.fungible.get(&Concrete(test_id.clone())).copied().unwrap_or_default();
+        let paid = payment - unused;
+
+        let refunded = match trader.refund_weight(weight) {
+            Some(MultiAsset { fun: Fungible(amount), .. }) => amount,
+            _ => 0,
+        };
+        proptest::prop_assert!(refunded <= paid, "refunded {} > paid {}", refunded, paid);
+        proptest::prop_assert!(trader.paid_assets.is_empty());
+    }
+}
+
+fn some_payment() -> impl proptest::strategy::Strategy<Value = Assets> {
+    use proptest::prelude::*;
+    let id = prop_oneof![
+        Just(CORE_ASSET_ID),
+        Just(TEST_ASSET_ID),
+        Just(CHEAP_ASSET_ID),
+        Just(OVERFLOW_ASSET_ID),
+        // unknown asset
+        Just(999),
+    ];
+    let fun = prop_oneof![
+        Just(Fungible(0)),
+        (1..2_000_000u128).prop_map(Fungible),
+        (0..u128::MAX).prop_map(Fungible),
+        (0..3u128).prop_map(|index| NonFungible(AssetInstance::Index(index))),
+    ];
+    // Duplicates are merged by `Assets::subsume`, just like the executor does for holding.
+    prop::collection::vec((id, fun), 0..6).prop_map(|assets| {
+        let mut payment = Assets::new();
+        for (id, fun) in assets {
+            let location = MockConvert::convert(id).unwrap_or_else(|| GeneralIndex(id.into()).into());
+            payment.subsume(MultiAsset {
+                id: Concrete(location),
+                fun,
+            });
+        }
+        payment
+    })
+}
+
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(1000))]
+    #[test]
+    fn buy_weight_handles_arbitrary_payments(
+        weight in 0..2_000_000u64,
+        payment in some_payment(),
+    ) {
+        type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+        let mut trader = Trader::new();
+        match trader.buy_weight(weight, payment.clone()) {
+            Ok(unused) => {
+                // Non-fungible assets are never charged.
+                proptest::prop_assert_eq!(&unused.non_fungible, &payment.non_fungible);
+                // Only assets of the payment are returned and never more than was paid.
+                let mut charged = BTreeMap::new();
+                for (id, unused_amount) in unused.fungible.iter() {
+                    let paid_amount = payment.fungible.get(id).copied();
+                    proptest::prop_assert!(paid_amount.map_or(false, |paid| *unused_amount <= paid));
+                }
+                for (id, paid_amount) in payment.fungible.iter() {
+                    let unused_amount = unused.fungible.get(id).copied().unwrap_or_default();
+                    if let Concrete(location) = id {
+                        *charged.entry(location.clone()).or_insert(0u128) += paid_amount - unused_amount;
+                    }
+                }
+                charged.retain(|_, amount| *amount > 0);
+                // Exactly the charged amounts are tracked.
+                let mut tracked = BTreeMap::new();
+                for (location, _, amount) in trader.paid_assets() {
+                    *tracked.entry(location.clone()).or_insert(0u128) += amount;
+                }
+                tracked.retain(|_, amount| *amount > 0);
+                proptest::prop_assert_eq!(charged, tracked);
+                proptest::prop_assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(weight));
+            }
+            Err(_) => {
+                // A failed buy does not change the state.
+                proptest::prop_assert!(trader.paid_assets.is_empty());
+                proptest::prop_assert_eq!(trader.bought_weight(), WeightV2::zero());
+            }
+        }
+    }
+}
+
+#[test]
+fn refunds_from_seeded_state_are_capped_by_paid_amount_and_weight() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let half = Price::saturating_from_rational(1, 2);
+    // less of the core asset was paid than the bought weight is worth in it
+    let mut trader = Trader::with_state(
+        1_500,
+        BTreeMap::from([((core_id.clone(), Price::one()), 1_000), ((test_id.clone(), half), 300)]),
+    );
+
+    // the refund of the core asset is capped by the paid amount
+    let expected_refund: MultiAsset = (Concrete(core_id), 1_000).into();
+    assert_eq!(trader.refund_weight(1_200), Some(expected_refund));
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 100).into();
+    assert_eq!(trader.refund_weight(200), Some(expected_refund));
+    // only the remaining weight is refunded, the rest stays with the trader
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 50).into();
+    assert_eq!(trader.refund_weight(1_000), Some(expected_refund));
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+    assert_eq!(trader.paid_assets().collect::<Vec<_>>(), vec![(&test_id, &half, &150)]);
+}
+
+#[test]
+fn zero_amounts_are_not_tracked() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let half = Price::saturating_from_rational(1, 2);
+    let trader = Trader::with_state(
+        1_000,
+        BTreeMap::from([((core_id, Price::one()), 0), ((test_id.clone(), half), 500)]),
+    );
+
+    assert_eq!(trader.paid_assets().collect::<Vec<_>>(), vec![(&test_id, &half, &500)]);
+    let expected_revenue: MultiAsset = (test_id, 500).into();
+    assert_eq!(trader.pending_revenue(), vec![expected_revenue]);
+}
+
+#[test]
+fn partial_buy_buys_affordable_share_of_weight() {
+    use frame_support::traits::ConstBool;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type PartialBuy = ConstBool<true>;
+        }
+    }
+
+    type StrictTrader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+    type PartialTrader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let payment: MultiAsset = (Concrete(test_id.clone()), 250_000).into();
+
+    // buys are strict by default and fail if the payment does not cover the weight
+    let mut trader = StrictTrader::new();
+    let res = trader.buy_weight(1_000_000, payment.clone().into());
+    assert_eq!(res, Err(XcmError::TooExpensive));
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+
+    // partial buys buy half the weight with half the required payment
+    let mut trader = PartialTrader::new();
+    let unused = trader
+        .buy_weight(1_000_000, payment.into())
+        .expect("buy_weight should succeed");
+    assert!(unused.is_empty());
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(500_000));
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&test_id, &Price::from_float(0.5), &250_000)]
+    );
+
+    // partial buys of affordable weight buy all of it
+    let mut trader = PartialTrader::new();
+    let payment: MultiAsset = (Concrete(test_id.clone()), 600_000).into();
+    let unused = trader
+        .buy_weight(1_000_000, payment.into())
+        .expect("buy_weight should succeed");
+    let expected_unused: Assets = MultiAsset::from((Concrete(test_id), 100_000)).into();
+    assert_eq!(unused, expected_unused);
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_000_000));
+}
+
+#[test]
+fn partial_buy_fee_is_capped_at_payment_after_rounding() {
+    use frame_support::traits::ConstBool;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type ChargeIncrement = MockIncrement;
+            type PartialBuy = ConstBool<true>;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // 250_500 affords 501_000 weight, but its fee is rounded up to an increment of 1_000
+    let mut trader = Trader::new();
+    let payment: MultiAsset = (Concrete(test_id.clone()), 250_500).into();
+    let unused = trader
+        .buy_weight_v2(WeightV2::from_ref_time(1_000_000), payment.into())
+        .expect("buy_weight_v2 should succeed");
+    assert!(unused.is_empty());
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(501_000));
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&test_id, &Price::from_float(0.5), &250_500)]
+    );
+}
+
+#[test]
+fn overpayment_is_returned_as_exact_change() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    for (location, price, weight, payment, required) in [
+        (core_id, Price::one(), 600_000, 1_000_000, 600_000),
+        // the fee of 166_666.5 is truncated
+        (test_id, Price::from_float(0.5), 333_333, 200_000, 166_666),
+    ] {
+        let mut trader = Trader::new();
+        let unused = trader
+            .buy_weight(weight, (Concrete(location.clone()), payment).into())
+            .expect("buy_weight should succeed");
+        let expected_unused: Assets = MultiAsset::from((Concrete(location.clone()), payment - required)).into();
+        assert_eq!(unused, expected_unused);
+        // the recorded revenue plus the change is the original payment
+        assert_eq!(
+            trader.paid_assets().collect::<Vec<_>>(),
+            vec![(&location, &price, &required)]
+        );
+    }
+}
+
+#[test]
+fn tiny_weight_is_charged_at_least_one_unit() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    // 0.5 * 1 = 0.5 would be truncated to 0
+    let mut trader = Trader::new();
+    let unused = trader
+        .buy_weight(1, (Concrete(test_id.clone()), 10).into())
+        .expect("buy_weight should succeed");
+    let expected_unused: Assets = MultiAsset::from((Concrete(test_id.clone()), 9)).into();
+    assert_eq!(unused, expected_unused);
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&test_id, &Price::from_float(0.5), &1)]
+    );
+
+    // buying no weight is still free
+    assert_eq!(
+        Trader::compute_fee(WeightV2::zero(), &test_id, Price::from_float(0.5)),
+        Ok(0)
+    );
+    // and does not touch the payment or the trader
+    let mut trader = Trader::new();
+    let payment: Assets = MultiAsset::from((Concrete(test_id), 10)).into();
+    assert_eq!(trader.buy_weight(0, payment.clone()), Ok(payment));
+    assert!(trader.paid_assets().next().is_none());
+    assert_eq!((trader.buys(), trader.last_buy_id()), (0, None));
+}
+
+#[test]
+fn buy_ids_do_not_wrap_around() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let payment: Assets = MultiAsset::from((Concrete(test_id.clone()), 1_000_000)).into();
+
+    let mut trader = Trader::new();
+    trader
+        .buy_weight(1_000_000, payment.clone())
+        .expect("buy_weight should succeed");
+    trader.buys = u32::MAX;
+    let paid_assets = trader.paid_assets.clone();
+    let weight = trader.weight;
+
+    // another buy would reuse the id of an earlier one
+    assert_eq!(trader.buy_weight(1_000_000, payment), Err(XcmError::Overflow));
+    assert_eq!(trader.paid_assets, paid_assets);
+    assert_eq!(trader.weight, weight);
+    assert_eq!(trader.buys(), u32::MAX);
+}
+
+#[test]
+fn revenue_is_consolidated_per_location() {
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, ProptestOracle, MockConvert, OtherRevenue>;
+
+    OTHER_REVENUE.with(|r| r.borrow_mut().clear());
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+        // the price changes between the buys
+        PROPTEST_PRICE.with(|p| *p.borrow_mut() = Price::from_float(0.5));
+        let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        PROPTEST_PRICE.with(|p| *p.borrow_mut() = Price::saturating_from_integer(2));
+        let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 2_000_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        assert_eq!(trader.paid_assets().count(), 2);
+    }
+
+    let expected_revenue: MultiAsset = (Concrete(test_id), 2_500_000).into();
+    OTHER_REVENUE.with(|r| assert_eq!(*r.borrow(), vec![expected_revenue]));
+}
+
+#[test]
+fn duplicate_payment_entries_are_combined() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let payment = Assets::from(vec![
+        (Concrete(test_id.clone()), 300_000).into(),
+        (Concrete(test_id.clone()), 300_000).into(),
+    ]);
+
+    // neither entry covers the fee of 500_000 on its own
+    let mut trader = Trader::new();
+    let unused = trader
+        .buy_weight(1_000_000, payment)
+        .expect("buy_weight should succeed");
+    let expected_unused: Assets = MultiAsset::from((Concrete(test_id.clone()), 100_000)).into();
+    assert_eq!(unused, expected_unused);
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&test_id, &Price::from_float(0.5), &500_000)]
+    );
+}
+
+#[test]
+fn pending_revenue_reports_paid_assets_without_taking_them() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert!(trader.pending_revenue().is_empty());
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+
+    let expected: Vec<MultiAsset> = vec![
+        (Concrete(core_id), 1_000_000).into(),
+        (Concrete(test_id), 500_000).into(),
+    ];
+    assert_eq!(trader.pending_revenue(), expected);
+    // nothing was taken or changed
+    ExpectRevenue::expect_no_revenue();
+    assert_eq!(trader.pending_revenue(), expected);
+}
+
+#[test]
+fn reset_discards_state_without_taking_revenue() {
+    ExpectRevenue::reset();
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ExpectRevenue>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+        let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+
+        trader.reset();
+        assert_eq!(trader.bought_weight(), WeightV2::zero());
+        assert_eq!(trader.paid_assets().count(), 0);
+        assert_eq!(trader.buys(), 0);
+        assert_eq!(trader.refund_weight(1_000_000), None);
+    }
+    ExpectRevenue::expect_no_revenue();
+}
+
+#[test]
+fn refund_weight_multi_drains_assets_one_after_the_other() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let cheap_id = MockConvert::convert(CHEAP_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    for payment in [
+        (Concrete(core_id.clone()), 1_000_000),
+        (Concrete(test_id.clone()), 500_000),
+        (Concrete(cheap_id.clone()), 4_000_000),
+    ] {
+        let res = trader.buy_weight(1_000_000, MultiAsset::from(payment).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+    }
+
+    // the core and test assets cover 2_000_000 weight together
+    let expected_refunds: Vec<MultiAsset> = vec![
+        (Concrete(core_id), 1_000_000).into(),
+        (Concrete(test_id), 500_000).into(),
+    ];
+    assert_eq!(trader.refund_weight_multi(2_000_000), expected_refunds);
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_000_000));
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&cheap_id, &Price::saturating_from_integer(4), &4_000_000)]
+    );
+
+    // only the remaining weight is refunded
+    let expected_refunds: Vec<MultiAsset> = vec![(Concrete(cheap_id), 4_000_000).into()];
+    assert_eq!(trader.refund_weight_multi(2_000_000), expected_refunds);
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+    assert_eq!(trader.refund_weight_multi(1_000), vec![]);
+}
+
+#[test]
+fn refund_buy_refunds_the_named_buy() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert_eq!(trader.last_buy_id(), None);
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let first = trader.last_buy_id().expect("buy should have an id");
+    let res = trader.buy_weight(1_000_000, (Concrete(test_id.clone()), 500_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let second = trader.last_buy_id().expect("buy should have an id");
+    assert!(second > first);
+
+    // the second buy is refunded although the core asset comes first
+    let expected_refund: MultiAsset = (Concrete(test_id.clone()), 200_000).into();
+    assert_eq!(trader.refund_buy(second, 400_000), Some(expected_refund));
+    // at most the weight of the buy is refunded
+    let expected_refund: MultiAsset = (Concrete(test_id), 300_000).into();
+    assert_eq!(trader.refund_buy(second, 1_000_000), Some(expected_refund));
+    assert_eq!(trader.refund_buy(second, 1_000_000), None);
+
+    // the first buy is untouched
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_000_000));
+    assert_eq!(
+        trader.paid_assets().collect::<Vec<_>>(),
+        vec![(&core_id, &Price::one(), &1_000_000)]
+    );
+}
+
+#[test]
+fn refund_buy_accounts_for_other_refunds() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let first = trader.last_buy_id().expect("buy should have an id");
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    let second = trader.last_buy_id().expect("buy should have an id");
+
+    // refund_weight refunds the most recent buy first
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 1_500_000).into();
+    assert_eq!(trader.refund_weight(1_500_000), Some(expected_refund));
+    assert_eq!(trader.refunds(), 1);
+
+    // the second buy was fully refunded, the first one only has half of its payment left
+    assert_eq!(trader.refund_buy(second, 1_000_000), None);
+    // zero refunds are not counted
+    assert_eq!(trader.refund_buy(first, 0), None);
+    assert_eq!(trader.refunds(), 1);
+    let expected_refund: MultiAsset = (Concrete(core_id), 500_000).into();
+    assert_eq!(trader.refund_buy(first, 1_000_000), Some(expected_refund));
+    assert_eq!(trader.refunds(), 2);
+    assert_eq!(trader.bought_weight(), WeightV2::zero());
+    assert_eq!(trader.paid_assets().count(), 0);
+}
+
+#[test]
+fn abstract_revenue_is_deposited_to_receiver() {
+    use frame_support::traits::Everything;
+
+    ExpectDeposit::reset();
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    /// Converts the abstract id `b"test"` to the test asset.
+    struct MockAbstractConvert;
+    impl Convert<Vec<u8>, Option<AssetId>> for MockAbstractConvert {
+        fn convert(key: Vec<u8>) -> Option<AssetId> {
+            (key == b"test".to_vec()).then_some(TEST_ASSET_ID)
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        (),
+        (),
+        MockAbstractConvert,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    // concrete and abstract assets are both deposited
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1234);
+    ExpectDeposit::register_expected_fee(42, TEST_ASSET_ID, 5678);
+    Revenue::take_revenue((core_id, 1234).into());
+    Revenue::take_revenue((Abstract(b"test".to_vec()), 5678).into());
+    assert_that_fee_is_deposited!();
+
+    // unknown abstract assets are not deposited (`ExpectDeposit` would panic)
+    Revenue::take_revenue((Abstract(b"unknown".to_vec()), 5678).into());
+    assert_that_fee_is_deposited!();
+}
+
+#[test]
+fn revenue_below_min_deposit_is_skipped() {
+    use frame_support::traits::{ConstU128, Everything};
+
+    ExpectDeposit::reset();
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        (),
+        (),
+        (),
+        ConstU128<1_000>,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    // amounts at the threshold are deposited
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 1_000);
+    Revenue::take_revenue((core_id.clone(), 1_000).into());
+    assert_that_fee_is_deposited!();
+
+    // amounts below are skipped (`ExpectDeposit` would panic)
+    Revenue::take_revenue((core_id, 999).into());
+    assert_that_fee_is_deposited!();
+}
+
+thread_local! {
+    pub static FAILED_DEPOSITS: RefCell<Vec<(AccountId, AssetId, Balance)>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn failed_deposits_are_passed_to_failure_handler() {
+    use crate::revenue::OnDepositFailure;
+    use frame_support::traits::Everything;
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    struct FailingDeposit;
+    impl DepositFee<AccountId, AssetId, Balance> for FailingDeposit {
+        fn deposit_fee(_who: &AccountId, _asset: AssetId, _amount: Balance) -> DispatchResult {
+            Err("deposit failed".into())
+        }
+    }
+
+    /// Records the failed deposits in `FAILED_DEPOSITS`.
+    struct RecordFailure;
+    impl OnDepositFailure<AccountId, AssetId, Balance> for RecordFailure {
+        fn on_deposit_failure(receiver: &AccountId, asset: AssetId, amount: Balance) {
+            FAILED_DEPOSITS.with(|f| f.borrow_mut().push((*receiver, asset, amount)));
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        FailingDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        (),
+        (),
+        (),
+        (),
+        RecordFailure,
+    >;
+
+    FAILED_DEPOSITS.with(|f| f.borrow_mut().clear());
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    Revenue::take_revenue((test_id, 1234).into());
+
+    FAILED_DEPOSITS.with(|f| assert_eq!(*f.borrow(), vec![(42, TEST_ASSET_ID, 1234)]));
+}
+
+#[test]
+fn revenue_is_deposited_to_account() {
+    use crate::revenue::ToAccount;
+    use frame_support::traits::ConstU32;
+
+    ExpectDeposit::reset();
+
+    type Revenue = ToAccount<AccountId, AssetId, Balance, MockConvert, ExpectDeposit, ConstU32<7>>;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    ExpectDeposit::register_expected_fee(7, TEST_ASSET_ID, 1234);
+    Revenue::take_revenue((test_id, 1234).into());
+    assert_that_fee_is_deposited!();
+}
+
+#[test]
+fn round_robin_receiver_rotates_accounts() {
+    use crate::revenue::RoundRobinReceiver;
+    use frame_support::{parameter_types, storage_alias};
+
+    #[storage_alias]
+    type ReceiverIndex = StorageValue<Test, u32, ValueQuery>;
+
+    parameter_types! {
+        pub Accounts: Vec<AccountId> = vec![1, 2, 3];
+    }
+
+    type Revenue = RoundRobinReceiver<AccountId, AssetId, Balance, MockConvert, ExpectDeposit, Accounts, ReceiverIndex>;
+
+    sp_io::TestExternalities::default().execute_with(|| {
+        ExpectDeposit::reset();
+        let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+        // the fourth deposit goes to the first account again
+        for (account, amount) in [(1, 100), (2, 200), (3, 300), (1, 400)] {
+            ExpectDeposit::register_expected_fee(account, CORE_ASSET_ID, amount);
+            Revenue::take_revenue((core_id.clone(), amount).into());
+            assert_that_fee_is_deposited!();
+        }
+        assert_eq!(ReceiverIndex::get(), 1);
+    });
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn buy_and_refund_enter_tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records the names of entered spans.
+    #[derive(Clone, Default)]
+    struct RecordSpans {
+        names: Arc<Mutex<Vec<&'static str>>>,
+        entered: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Subscriber for RecordSpans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut names = self.names.lock().unwrap();
+            names.push(span.metadata().name());
+            Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            let name = self.names.lock().unwrap()[span.into_u64() as usize - 1];
+            self.entered.lock().unwrap().push(name);
+        }
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let subscriber = RecordSpans::default();
+    let entered = subscriber.entered.clone();
+
+    tracing::subscriber::with_default(subscriber, || {
+        let mut trader = Trader::new();
+        let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+        assert!(trader.refund_weight(1_000_000).is_some());
+    });
+
+    assert_eq!(
+        *entered.lock().unwrap(),
+        vec![
+            "MultiCurrencyTrader::buy_weight_v2",
+            "MultiCurrencyTrader::refund_weight_v2"
+        ]
+    );
+}
+
+#[test]
+fn check_invariants_detects_inconsistent_state() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    assert_eq!(trader.check_invariants(), Ok(()));
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 1_000_000).into());
+    assert!(res.expect("buy_weight should succeed").is_empty());
+    assert_eq!(trader.check_invariants(), Ok(()));
+
+    let trader = Trader::with_state(
+        1_500,
+        BTreeMap::from([
+            ((core_id.clone(), Price::one()), 1_000),
+            ((test_id.clone(), Price::one()), 500),
+        ]),
+    );
+    assert_eq!(trader.check_invariants(), Ok(()));
+
+    let trader = Trader::with_state(1_500, BTreeMap::from([((test_id.clone(), Price::one()), 0)]));
+    assert_eq!(trader.check_invariants(), Err("Tracked amount is zero"));
+
+    let mut trader = Trader::with_state(1_500, BTreeMap::new());
+    trader.paid_assets.entry(test_id, Price::one()).amount = 500;
+    assert_eq!(trader.check_invariants(), Err("Tracked amount was not paid by a buy"));
+
+    let mut trader = Trader::new();
+    trader.weight = WeightV2::from_ref_time(1_000);
+    assert_eq!(
+        trader.check_invariants(),
+        Err("Weight or amounts tracked without a buy")
+    );
+}
+
+#[cfg(feature = "runtime-benchmarks")]
+#[test]
+fn benchmarking_helpers_set_up_trader() {
+    use crate::benchmarking::{payment, trader_tracking};
+
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let locations: Vec<MultiLocation> = [CORE_ASSET_ID, TEST_ASSET_ID, CHEAP_ASSET_ID]
+        .into_iter()
+        .map(|id| MockConvert::convert(id).unwrap())
+        .collect();
+
+    let mut trader = Trader::new();
+    let res = trader.buy_weight(1_000, payment(locations.clone(), 10_000));
+    assert_eq!(res.expect("buy_weight should succeed").fungible.len(), 3);
+
+    let trader: Trader = trader_tracking(locations, 1_000, 10_000).expect("buys should succeed");
+    assert_eq!(trader.paid_assets().count(), 3);
+}
+
+#[test]
+fn min_oracle_returns_lower_available_price() {
+    use crate::oracles::MinOracle;
+
+    /// Prices the test asset higher and the cheap asset lower than `MockOracle`, and has no price
+    /// for the core asset.
+    struct OtherOracle;
+    impl NativePriceOracle<AssetId, Price> for OtherOracle {
+        fn price(currency: AssetId) -> Option<Price> {
+            match currency {
+                TEST_ASSET_ID => Some(Price::one()),
+                CHEAP_ASSET_ID => Some(Price::saturating_from_integer(2)),
+                OVERFLOW_ASSET_ID => None,
+                _ => Some(Price::saturating_from_integer(3)),
+            }
+        }
+    }
+
+    type Oracle = MinOracle<MockOracle, OtherOracle>;
+    // spot < ema
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::from_float(0.5)));
+    // ema < spot
+    assert_eq!(Oracle::price(CHEAP_ASSET_ID), Some(Price::saturating_from_integer(2)));
+    // only one is available
+    assert_eq!(
+        Oracle::price(OVERFLOW_ASSET_ID),
+        Some(Price::saturating_from_integer(2_147_483_647))
+    );
+    assert_eq!(Oracle::price(9876), Some(Price::saturating_from_integer(3)));
+    assert_eq!(MinOracle::<MockOracle, MockOracle>::price(9876), None);
+}
+
+#[test]
+fn lp_share_oracle_values_shares_by_pool_reserves() {
+    use crate::oracles::{LiquidityPoolShares, LpShareOracle};
+
+    const LP_ASSET_ID: AssetId = 5000;
+    const EMPTY_LP_ASSET_ID: AssetId = 5001;
+    const UNPRICED_LP_ASSET_ID: AssetId = 5002;
+
+    struct MockPool;
+    impl LiquidityPoolShares<AssetId> for MockPool {
+        type Balance = Balance;
+
+        fn pool_of(share_asset: &AssetId) -> Option<(Vec<(AssetId, Balance)>, Balance)> {
+            match *share_asset {
+                LP_ASSET_ID => Some((vec![(CORE_ASSET_ID, 1_000), (TEST_ASSET_ID, 500)], 4_000)),
+                EMPTY_LP_ASSET_ID => Some((vec![], 0)),
+                UNPRICED_LP_ASSET_ID => Some((vec![(CORE_ASSET_ID, 1_000), (9876, 1_000)], 1_000)),
+                _ => None,
+            }
+        }
+    }
+
+    type Oracle = LpShareOracle<MockPool, MockOracle>;
+    // the pool is worth 1_000 + 500 / 0.5 = 2_000 native, so one native buys 2 shares
+    assert_eq!(Oracle::price(LP_ASSET_ID), Some(Price::saturating_from_integer(2)));
+    assert_eq!(Oracle::price(EMPTY_LP_ASSET_ID), None);
+    assert_eq!(Oracle::price(UNPRICED_LP_ASSET_ID), None);
+    // other assets are priced by the underlying oracle
+    assert_eq!(Oracle::price(CORE_ASSET_ID), Some(Price::one()));
+    assert_eq!(Oracle::price(TEST_ASSET_ID), Some(Price::from_float(0.5)));
+}
+
+thread_local! {
+    pub static REFERRER: RefCell<Option<AccountId>> = RefCell::new(None);
+}
+
+#[test]
+fn referral_split_shares_revenue_with_referrer() {
+    use crate::revenue::{DepositFeeTo, ReferralSplit, ReferrerLookup};
+    use sp_runtime::Permill;
+
+    struct MockReferrer;
+    impl ReferrerLookup for MockReferrer {
+        type AccountId = AccountId;
+
+        fn referrer() -> Option<AccountId> {
+            REFERRER.with(|r| *r.borrow())
+        }
+    }
+
+    parameter_types! {
+        pub ReferralShare: Permill = Permill::from_percent(10);
+    }
+
+    type Revenue = ReferralSplit<
+        ExpectRevenue,
+        DepositFeeTo<AssetId, Balance, MockConvert, ExpectDeposit>,
+        ReferralShare,
+        MockReferrer,
+    >;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let amount = 1_009;
+
+    // with a referrer, the rounding dust goes to the main receiver
+    ExpectRevenue::reset();
+    ExpectDeposit::reset();
+    REFERRER.with(|r| *r.borrow_mut() = Some(42));
+    let share = ReferralShare::get().mul_floor(amount);
+    assert_eq!(share, 100);
+    ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, share);
+    Revenue::take_revenue((core_id.clone(), amount).into());
+    assert_that_fee_is_deposited!();
+    let taken = TAKEN_REVENUE.with(|t| t.borrow().clone());
+    assert_eq!(taken, BTreeSet::from([(core_id.clone(), amount - share).into()]));
+
+    // without a referrer, everything goes to the main receiver
+    ExpectRevenue::reset();
+    ExpectDeposit::reset();
+    REFERRER.with(|r| *r.borrow_mut() = None);
+    Revenue::take_revenue((core_id.clone(), amount).into());
+    let taken = TAKEN_REVENUE.with(|t| t.borrow().clone());
+    assert_eq!(taken, BTreeSet::from([(core_id, amount).into()]));
+}
+
+#[test]
+fn prefixed_locations_resolve_to_the_same_asset() {
+    use crate::location::PrefixedLocationConverter;
+
+    parameter_types! {
+        pub SelfLocation: MultiLocation = MultiLocation::new(1, X1(Parachain(2_034)));
+    }
+
+    type Converter = PrefixedLocationConverter<SelfLocation, MockConvert>;
+
+    let key = TEST_ASSET_ID.encode();
+    let local = MultiLocation::new(0, X1(GeneralKey(key.clone().try_into().unwrap())));
+    let sibling = MultiLocation::new(1, X2(Parachain(2_034), GeneralKey(key.clone().try_into().unwrap())));
+    let other_sibling = MultiLocation::new(1, X2(Parachain(1_000), GeneralKey(key.try_into().unwrap())));
+
+    assert_eq!(Converter::convert(local.clone()), Some(TEST_ASSET_ID));
+    assert_eq!(Converter::convert(sibling.clone()), Some(TEST_ASSET_ID));
+    assert_eq!(Converter::convert(other_sibling), None);
+    assert_eq!(
+        Converter::convert(MultiAsset::from((sibling, 1_000))),
+        Some(TEST_ASSET_ID)
+    );
+    assert_eq!(
+        Converter::convert(MultiAsset::from((local, 1_000))),
+        Some(TEST_ASSET_ID)
+    );
+}
+
+thread_local! {
+    pub static CONVERSIONS: RefCell<Vec<MultiLocation>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn assets_are_converted_once_per_buy() {
+    /// Records the locations it converts.
+    struct CountingConvert;
+    impl Convert<MultiAsset, Option<AssetId>> for CountingConvert {
+        fn convert(asset: MultiAsset) -> Option<AssetId> {
+            if let Concrete(location) = &asset.id {
+                CONVERSIONS.with(|c| c.borrow_mut().push(location.clone()));
+            }
+            MockConvert::convert(asset)
+        }
+    }
+
+    ExpectRevenue::reset();
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type Selector = CheapestAssetSelector;
+        }
+    }
+
+    type Trader = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        CountingConvert,
+        ExpectRevenue,
+        Config,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let conversions_of =
+        |location: &MultiLocation| CONVERSIONS.with(|c| c.borrow().iter().filter(|l| *l == location).count());
+
+    let mut trader = Trader::new();
+    let mut payment = Assets::new();
+    payment.subsume((Concrete(test_id.clone()), 1_000).into());
+    payment.subsume((Concrete(core_id.clone()), 2_000).into());
+    let unused = trader.buy_weight(1_000, payment).expect("buy_weight should succeed");
+    // the test asset is the cheapest
+    let mut expected = Assets::new();
+    expected.subsume((Concrete(test_id.clone()), 500).into());
+    expected.subsume((Concrete(core_id.clone()), 2_000).into());
+    assert_eq!(unused, expected);
+    assert_eq!(conversions_of(&test_id), 1);
+    assert_eq!(conversions_of(&core_id), 1);
+
+    // every buy converts afresh
+    let unused = trader
+        .buy_weight(1_000, (test_id.clone(), 500).into())
+        .expect("buy_weight should succeed");
+    assert!(unused.is_empty());
+    assert_eq!(conversions_of(&test_id), 2);
+}
+
+#[test]
+fn pallet_instance_index_converter_round_trips_asset_ids() {
+    use crate::location::PalletInstanceIndexConverter;
+    use frame_support::traits::ConstU8;
+
+    type Converter = PalletInstanceIndexConverter<ConstU8<50>, MockAssets>;
+
+    let location = MultiLocation::new(0, X2(PalletInstance(50), GeneralIndex(TEST_ASSET_ID.into())));
+    assert_eq!(Converter::convert(TEST_ASSET_ID), Some(location.clone()));
+    assert_eq!(Converter::convert(location.clone()), Some(TEST_ASSET_ID));
+    assert_eq!(
+        Converter::convert(MultiAsset::from((location, 1_000))),
+        Some(TEST_ASSET_ID)
+    );
+
+    // unknown assets and other pallets are not converted
+    let unknown_id: AssetId = 9876;
+    assert_eq!(Converter::convert(unknown_id), None);
+    let unknown = MultiLocation::new(0, X2(PalletInstance(50), GeneralIndex(unknown_id.into())));
+    assert_eq!(Converter::convert(unknown), None::<AssetId>);
+    let other_pallet = MultiLocation::new(0, X2(PalletInstance(51), GeneralIndex(TEST_ASSET_ID.into())));
+    assert_eq!(Converter::convert(other_pallet), None::<AssetId>);
+}
+
+#[test]
+fn surplus_is_credited_to_later_buys() {
+    use frame_support::traits::ConstBool;
+
+    struct CreditConfig<AccumulateCredit>(PhantomData<AccumulateCredit>);
+    trader_config! {
+        impl<AccumulateCredit: Get<bool>> TraderConfig<AssetId, Balance, Price> for CreditConfig<AccumulateCredit> {
+            type ChargeIncrement = MockIncrement;
+            type AccumulateCredit = AccumulateCredit;
+        }
+    }
+
+    type CreditTrader<AccumulateCredit> = MultiCurrencyTrader<
+        AssetId,
+        Balance,
+        Price,
+        IdentityFee<Balance>,
+        MockOracle,
+        MockConvert,
+        (),
+        CreditConfig<AccumulateCredit>,
+    >;
+
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+    let payment: MultiAsset = (Concrete(test_id.clone()), 1_000_000).into();
+
+    // 1_001_000 weight is worth 500_500, which is rounded up to 501_000 by the increment
+    let mut trader = CreditTrader::<()>::new();
+    trader.buy_weight(1_001_000, payment.clone().into()).unwrap();
+    trader.buy_weight(1_001_000, payment.clone().into()).unwrap();
+    assert_eq!(
+        trader.paid_assets().map(|(_, _, amount)| *amount).sum::<u128>(),
+        1_002_000
+    );
+
+    // the surplus of the first buy is credited to the second one
+    let mut trader = CreditTrader::<ConstBool<true>>::new();
+    let unused = trader.buy_weight(1_001_000, payment.clone().into()).unwrap();
+    assert_eq!(unused, MultiAsset::from((Concrete(test_id.clone()), 499_000)).into());
+    let unused = trader.buy_weight(1_001_000, payment.clone().into()).unwrap();
+    assert_eq!(unused, MultiAsset::from((Concrete(test_id.clone()), 499_500)).into());
+    assert_eq!(
+        trader.paid_assets().map(|(_, _, amount)| *amount).sum::<u128>(),
+        1_001_500
+    );
+
+    // the credit makes up for a payment short of the rounded fee
+    let short_payment: Assets = MultiAsset::from((Concrete(test_id.clone()), 500_500)).into();
+    let mut trader = CreditTrader::<()>::new();
+    let res = trader.buy_weight(1_001_000, short_payment.clone());
+    assert_eq!(res, Err(XcmError::TooExpensive));
+    let mut trader = CreditTrader::<ConstBool<true>>::new();
+    trader.buy_weight(1_001_000, payment.into()).unwrap();
+    assert_eq!(
+        trader.peek_fee(WeightV2::from_ref_time(1_001_000), &short_payment),
+        Ok((test_id, 500_500))
+    );
+    let unused = trader.buy_weight(1_001_000, short_payment).unwrap();
+    assert!(unused.is_empty());
+}
+
+#[test]
+fn refund_weight_accumulated_combines_refunds_of_the_same_asset() {
+    type Trader = MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, ()>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+    let test_id = MockConvert::convert(TEST_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+    for payment in [
+        (Concrete(core_id.clone()), 1_000_000),
+        (Concrete(test_id.clone()), 500_000),
+    ] {
+        let res = trader.buy_weight(1_000_000, MultiAsset::from(payment).into());
+        assert!(res.expect("buy_weight should succeed").is_empty());
+    }
+
+    // two partial refunds of the core asset are combined
+    let expected_refunds: Vec<MultiAsset> = vec![(Concrete(core_id.clone()), 500_000).into()];
+    assert_eq!(trader.refund_weight_accumulated([300_000, 200_000]), expected_refunds);
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_500_000));
+
+    // refunds of different assets are not
+    let expected_refunds: Vec<MultiAsset> =
+        vec![(Concrete(core_id), 500_000).into(), (Concrete(test_id), 100_000).into()];
+    assert_eq!(trader.refund_weight_accumulated([500_000, 200_000]), expected_refunds);
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(800_000));
+}
+
+thread_local! {
+    pub static OBSERVED: RefCell<Vec<(&'static str, MultiLocation, u128)>> = RefCell::new(Vec::new());
+}
+
+#[test]
+fn observer_is_notified_about_buys_refunds_and_revenue() {
+    /// Records all calls in `OBSERVED`.
+    struct RecordingObserver;
+    impl Observer for RecordingObserver {
+        fn on_buy(asset: &MultiLocation, amount: u128) {
+            OBSERVED.with(|o| o.borrow_mut().push(("buy", asset.clone(), amount)));
+        }
+
+        fn on_refund(asset: &MultiLocation, amount: u128) {
+            OBSERVED.with(|o| o.borrow_mut().push(("refund", asset.clone(), amount)));
+        }
+
+        fn on_revenue(asset: &MultiLocation, amount: u128) {
+            OBSERVED.with(|o| o.borrow_mut().push(("revenue", asset.clone(), amount)));
+        }
+    }
+
+    struct MockFeeReceiver;
+    impl TransactionMultiPaymentDataProvider<AccountId, AssetId, Price> for MockFeeReceiver {
+        fn get_currency_and_price(_who: &AccountId) -> Result<(AssetId, Option<Price>), DispatchError> {
+            Err("not implemented".into())
+        }
+
+        fn get_fee_receiver() -> AccountId {
+            42
+        }
+    }
+
+    type Revenue = ToFeeReceiver<
+        AccountId,
+        AssetId,
+        Balance,
+        Price,
+        MockConvert,
+        ExpectDeposit,
+        MockFeeReceiver,
+        Everything,
+        (),
+        (),
+        (),
+        (),
+        (),
+        (),
+        RecordingObserver,
+    >;
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type FeeObserver = RecordingObserver;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, Revenue, Config>;
+
+    ExpectDeposit::reset();
+    OBSERVED.with(|o| o.borrow_mut().clear());
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    {
+        let mut trader = Trader::new();
+        let payment: MultiAsset = (Concrete(core_id.clone()), 2_000_000).into();
+        trader
+            .buy_weight(1_000_000, payment.into())
+            .expect("buy_weight should succeed");
+        let expected_refund: MultiAsset = (Concrete(core_id.clone()), 400_000).into();
+        assert_eq!(trader.refund_weight(400_000), Some(expected_refund));
+        ExpectDeposit::register_expected_fee(42, CORE_ASSET_ID, 600_000);
+    }
+    assert_that_fee_is_deposited!();
+
+    // the revenue is observed once, by the fee receiver that deposited it
+    OBSERVED.with(|o| {
+        assert_eq!(
+            *o.borrow(),
+            vec![
+                ("buy", core_id.clone(), 1_000_000),
+                ("refund", core_id.clone(), 400_000),
+                ("revenue", core_id, 600_000),
+            ]
+        )
+    });
+}
+
+#[test]
+fn weight_of_the_trader_itself_is_charged_and_not_refunded() {
+    /// Buying costs 1_000 weight and refunding 500 weight per asset.
+    struct MockWeightInfo;
+    impl weights::WeightInfo for MockWeightInfo {
+        fn buy_weight(n: u32) -> WeightV2 {
+            WeightV2::from_ref_time(1_000 * n as u64)
+        }
+
+        fn refund_weight(n: u32) -> WeightV2 {
+            WeightV2::from_ref_time(500 * n as u64)
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type WeightInfo = MockWeightInfo;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    // the buy of a single asset costs 1_000 weight on top
+    let res = trader.buy_weight(1_000_000, (Concrete(core_id.clone()), 2_000_000).into());
+    let expected_unused: MultiAsset = (Concrete(core_id.clone()), 999_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_001_000));
+    assert_eq!(Trader::estimate_fee(1_000_000, &core_id), Some(1_001_000));
+
+    // the refund from a single tracked asset costs 500 weight
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 399_500).into();
+    assert_eq!(trader.refund_weight(400_000), Some(expected_refund));
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(601_500));
+
+    // refunding all remaining weight leaves the weight of the buy
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 599_500).into();
+    assert_eq!(trader.refund_weight(600_000), Some(expected_refund));
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(2_000));
+    let expected_refund: MultiAsset = (Concrete(core_id.clone()), 1_000).into();
+    assert_eq!(trader.refund_weight(u64::MAX), Some(expected_refund));
+    assert_eq!(trader.bought_weight(), WeightV2::from_ref_time(1_000));
+
+    // the weight of the buy is not refundable
+    let expected_revenue: MultiAsset = (Concrete(core_id), 1_000).into();
+    assert_eq!(trader.pending_revenue(), vec![expected_revenue]);
+}
+
+#[test]
+fn max_buy_weight_includes_the_weight_of_the_buy() {
+    use frame_support::traits::ConstU64;
+
+    /// Buying costs 1_000 weight per asset.
+    struct MockWeightInfo;
+    impl weights::WeightInfo for MockWeightInfo {
+        fn buy_weight(n: u32) -> WeightV2 {
+            WeightV2::from_ref_time(1_000 * n as u64)
+        }
+
+        fn refund_weight(_n: u32) -> WeightV2 {
+            WeightV2::zero()
+        }
+    }
+
+    struct Config;
+    trader_config! {
+        impl TraderConfig<AssetId, Balance, Price> for Config {
+            type MaxBuyWeight = ConstU64<1_000_000>;
+            type WeightInfo = MockWeightInfo;
+        }
+    }
+
+    type Trader =
+        MultiCurrencyTrader<AssetId, Balance, Price, IdentityFee<Balance>, MockOracle, MockConvert, (), Config>;
+
+    let core_id = MockConvert::convert(CORE_ASSET_ID).unwrap();
+
+    let mut trader = Trader::new();
+
+    let payment: MultiAsset = (Concrete(core_id.clone()), 2_000_000).into();
+    let res = trader.buy_weight(1_000_000, payment.clone().into());
+    assert_eq!(res, Err(XcmError::WeightLimitReached(1_001_000)));
+
+    let res = trader.buy_weight(999_000, payment.into());
+    let expected_unused: MultiAsset = (Concrete(core_id), 1_000_000).into();
+    assert_eq!(res, Ok(expected_unused.into()));
+}
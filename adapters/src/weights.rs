@@ -0,0 +1,44 @@
+// This file is part of hydradx-adapters.
+
+// Copyright (C) 2022  Intergalactic, Limited (GIB).
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Weights of the work done by `MultiCurrencyTrader` itself (oracle reads, tracking payments).
+//!
+//! The weight of the trader depends on its configuration, so it is benchmarked by the runtime
+//! hosting it with the benchmarks in `benchmarking`. The runtime generates its implementation of
+//! `WeightInfo` from them like the weights of its pallets, e.g. with `benchmark pallet
+//! --pallet=hydradx-adapters --extrinsic=* --output=<runtime>/src/weights/trader.rs`.
+
+use frame_support::weights::Weight;
+
+/// Weight functions needed for `MultiCurrencyTrader`.
+pub trait WeightInfo {
+    /// Buying weight with a payment containing `n` assets.
+    fn buy_weight(n: u32) -> Weight;
+    /// Refunding weight from a trader tracking `n` assets.
+    fn refund_weight(n: u32) -> Weight;
+}
+
+// For backwards compatibility and tests
+impl WeightInfo for () {
+    fn buy_weight(_n: u32) -> Weight {
+        Weight::zero()
+    }
+
+    fn refund_weight(_n: u32) -> Weight {
+        Weight::zero()
+    }
+}